@@ -0,0 +1,291 @@
+use crate::error::{MyError, Result};
+use crate::types::{Account, DbConnection, PubInvite};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::warn;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    /// Key for signing session tokens, set via `SESSION_SECRET`. Without one,
+    /// tokens only last until the server restarts.
+    static ref SESSION_SECRET: Vec<u8> = match env::var("SESSION_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            warn!("SESSION_SECRET not set, using a random one");
+            let mut secret = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret
+        }
+    };
+    /// How long a session token is valid for, set via `SESSION_TOKEN_TTL_SECONDS`
+    static ref SESSION_TOKEN_TTL: i64 = env::var("SESSION_TOKEN_TTL_SECONDS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(7 * 24 * 60 * 60);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionClaims {
+    pub person_id: Uuid,
+    /// Set for registered accounts, missing for guests
+    pub account_id: Option<Uuid>,
+    /// Unix timestamp, in seconds
+    pub issued: i64,
+    /// Unix timestamp, in seconds
+    pub expires: i64,
+}
+
+/// What's inside a pub invite link
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InviteClaims {
+    pub invite_id: Uuid,
+    pub pub_id: Uuid,
+    /// Unix timestamp, in seconds
+    pub expires: i64,
+}
+
+/// Returned from `POST /api/session`, `/api/register` and `/api/login`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionToken {
+    pub token: String,
+    pub person_id: Uuid,
+    pub account_id: Option<Uuid>,
+    pub expires: i64,
+}
+
+/// Optional body for `POST /api/session`, to refresh an existing token rather
+/// than starting as a new guest
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SessionRequest {
+    pub token: Option<String>,
+}
+
+fn sign(payload: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(&SESSION_SECRET).expect("HMAC can take keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Tokens are `<base64 claims json>.<base64 HMAC-SHA256 of the first part>`
+fn encode_signed<T: Serialize>(claims: &T) -> Result<String> {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signature = URL_SAFE_NO_PAD.encode(sign(&payload).finalize().into_bytes());
+    Ok(format!("{payload}.{signature}"))
+}
+
+/// Checks the signature and unpacks the claims, failing with `invalid` for
+/// anything that's been tampered with or is the wrong kind of token
+fn decode_signed<T: DeserializeOwned>(token: &str, invalid: impl Fn() -> MyError) -> Result<T> {
+    let (payload, signature) = token.split_once('.').ok_or_else(&invalid)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+    sign(payload)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?)
+        .map_err(|_| invalid())
+}
+
+pub fn mint_token(person_id: Uuid, account_id: Option<Uuid>) -> Result<SessionToken> {
+    let now = Utc::now().timestamp();
+    let claims = SessionClaims {
+        person_id,
+        account_id,
+        issued: now,
+        expires: now + *SESSION_TOKEN_TTL,
+    };
+    Ok(SessionToken {
+        token: encode_signed(&claims)?,
+        person_id,
+        account_id,
+        expires: claims.expires,
+    })
+}
+
+pub fn verify_token(token: &str) -> Result<SessionClaims> {
+    let claims: SessionClaims = decode_signed(token, || {
+        MyError::Unauthorized("Invalid session token".to_string())
+    })?;
+    if claims.expires < Utc::now().timestamp() {
+        return Err(MyError::Unauthorized(
+            "Session token has expired".to_string(),
+        ));
+    }
+    Ok(claims)
+}
+
+pub fn mint_invite_token(invite: &PubInvite) -> Result<String> {
+    encode_signed(&InviteClaims {
+        invite_id: invite.id,
+        pub_id: invite.pub_id,
+        expires: invite.expires.timestamp(),
+    })
+}
+
+/// Only checks the token itself. Whether the invite's been revoked is up to
+/// the database.
+pub fn verify_invite_token(token: &str) -> Result<InviteClaims> {
+    let claims: InviteClaims =
+        decode_signed(token, || MyError::Forbidden("Invalid invite".to_string()))?;
+    if claims.expires < Utc::now().timestamp() {
+        return Err(MyError::Forbidden("This invite has expired".to_string()));
+    }
+    Ok(claims)
+}
+
+/// Like `verify_token`, but also checks the account hasn't logged out since
+/// the token was issued
+pub async fn verify_session<'a>(conn: &mut DbConnection<'a>, token: &str) -> Result<SessionClaims> {
+    let claims = verify_token(token)?;
+    if let Some(account_id) = claims.account_id {
+        if !Account::session_still_valid(conn, account_id, claims.issued).await? {
+            return Err(MyError::Unauthorized(
+                "Session token has been logged out".to_string(),
+            ));
+        }
+    }
+    Ok(claims)
+}
+
+/// Argon2 is deliberately slow, so this runs off the async workers
+pub async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| MyError::Other(anyhow::anyhow!(format!("Failed to hash password: {e}"))))
+    })
+    .await
+    .map_err(|e| MyError::Other(e.into()))?
+}
+
+pub async fn verify_password(password: String, hash: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&hash)
+            .map_err(|e| MyError::Other(anyhow::anyhow!(format!("Bad password hash: {e}"))))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    })
+    .await
+    .map_err(|e| MyError::Other(e.into()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite_token(expires: i64) -> String {
+        encode_signed(&InviteClaims {
+            invite_id: Uuid::new_v4(),
+            pub_id: Uuid::new_v4(),
+            expires,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn session_token_round_trips() {
+        let person_id = Uuid::new_v4();
+        let token = mint_token(person_id, Some(person_id)).unwrap();
+        let claims = verify_token(&token.token).unwrap();
+        assert_eq!(claims.person_id, person_id);
+        assert_eq!(claims.account_id, Some(person_id));
+        assert_eq!(claims.expires, token.expires);
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let token = mint_token(Uuid::new_v4(), None).unwrap().token;
+        let (payload, signature) = token.split_once('.').unwrap();
+        let flipped = if signature.starts_with('A') { "B" } else { "A" };
+        let tampered = format!("{payload}.{flipped}{}", &signature[1..]);
+        assert!(matches!(
+            verify_token(&tampered),
+            Err(MyError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn tampered_claims_are_rejected() {
+        let token = mint_token(Uuid::new_v4(), None).unwrap().token;
+        let (_, signature) = token.split_once('.').unwrap();
+        let now = Utc::now().timestamp();
+        let claims = SessionClaims {
+            person_id: Uuid::new_v4(),
+            account_id: Some(Uuid::new_v4()),
+            issued: now,
+            expires: now + 60,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        assert!(matches!(
+            verify_token(&format!("{payload}.{signature}")),
+            Err(MyError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn expired_session_token_is_rejected() {
+        let now = Utc::now().timestamp();
+        let token = encode_signed(&SessionClaims {
+            person_id: Uuid::new_v4(),
+            account_id: None,
+            issued: now - 120,
+            expires: now - 60,
+        })
+        .unwrap();
+        assert!(matches!(
+            verify_token(&token),
+            Err(MyError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn expired_invite_is_rejected() {
+        let token = invite_token(Utc::now().timestamp() - 60);
+        assert!(matches!(
+            verify_invite_token(&token),
+            Err(MyError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn invite_token_is_not_a_session_token() {
+        let token = invite_token(Utc::now().timestamp() + 60);
+        assert!(verify_invite_token(&token).is_ok());
+        assert!(matches!(
+            verify_token(&token),
+            Err(MyError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn session_token_is_not_an_invite() {
+        let token = mint_token(Uuid::new_v4(), None).unwrap().token;
+        assert!(matches!(
+            verify_invite_token(&token),
+            Err(MyError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for token in ["", ".", "nodot", "a.b", "!!!.???"] {
+            assert!(verify_token(token).is_err(), "{:?} was accepted", token);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::error::{MyError, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Keep in step with the backfill in `V00013__pub_code.sql`, though codes from
+/// there only have two digit numbers
+const ADJECTIVES: [&str; 32] = [
+    "amber",
+    "brass",
+    "cosy",
+    "crooked",
+    "dusty",
+    "golden",
+    "green",
+    "hazy",
+    "hoppy",
+    "jolly",
+    "lazy",
+    "little",
+    "lucky",
+    "mellow",
+    "merry",
+    "misty",
+    "noble",
+    "old",
+    "plucky",
+    "quiet",
+    "red",
+    "royal",
+    "rusty",
+    "silver",
+    "sleepy",
+    "snug",
+    "stout",
+    "sunny",
+    "tipsy",
+    "wandering",
+    "white",
+    "wild",
+];
+const NOUNS: [&str; 32] = [
+    "anchor",
+    "badger",
+    "barrel",
+    "bell",
+    "boar",
+    "cask",
+    "crown",
+    "dragon",
+    "drum",
+    "falcon",
+    "fiddle",
+    "fox",
+    "goose",
+    "griffin",
+    "hare",
+    "harp",
+    "hart",
+    "horse",
+    "kettle",
+    "lamb",
+    "lantern",
+    "lion",
+    "mermaid",
+    "otter",
+    "owl",
+    "plough",
+    "raven",
+    "rose",
+    "ship",
+    "swan",
+    "tankard",
+    "wheatsheaf",
+];
+const MIN_SLUG_LENGTH: usize = 3;
+const MAX_SLUG_LENGTH: usize = 40;
+
+/// A new join code, like `jolly-badger-4242`. These can clash, so whoever's
+/// storing it needs to be ready to try again.
+pub fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{}-{}-{}",
+        ADJECTIVES.choose(&mut rng).expect("there are adjectives"),
+        NOUNS.choose(&mut rng).expect("there are nouns"),
+        rng.gen_range(1000..10000)
+    )
+}
+
+/// Codes get read out and typed in by people, so be forgiving about case and
+/// spacing
+pub fn normalise_code(code: &str) -> String {
+    code.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// Slugs end up in URLs, so they're kept to lowercase letters, digits and
+/// dashes
+pub fn check_slug(slug: &str) -> Result<String> {
+    let slug = slug.trim().to_lowercase();
+    if !(MIN_SLUG_LENGTH..=MAX_SLUG_LENGTH).contains(&slug.len())
+        || !slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        || slug.starts_with('-')
+        || slug.ends_with('-')
+    {
+        return Err(MyError::InvalidInput(format!(
+            "Slugs need to be {MIN_SLUG_LENGTH} to {MAX_SLUG_LENGTH} letters, numbers or dashes"
+        )));
+    }
+    Ok(slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_codes_are_already_normalised() {
+        for _ in 0..20 {
+            let code = generate_code();
+            assert_eq!(normalise_code(&code), code);
+        }
+    }
+
+    #[test]
+    fn codes_forgive_case_and_spacing() {
+        assert_eq!(normalise_code("Jolly Badger 42"), "jolly-badger-42");
+        assert_eq!(normalise_code("  jolly   badger\t42 "), "jolly-badger-42");
+        assert_eq!(normalise_code("JOLLY-BADGER-42"), "jolly-badger-42");
+    }
+
+    #[test]
+    fn slugs_are_trimmed_and_lowercased() {
+        assert_eq!(check_slug("  The-Red-Lion ").unwrap(), "the-red-lion");
+        assert_eq!(check_slug("pub42").unwrap(), "pub42");
+    }
+
+    #[test]
+    fn bad_slugs_are_rejected() {
+        let too_long = "a".repeat(MAX_SLUG_LENGTH + 1);
+        for slug in [
+            "ab",
+            "-pub",
+            "pub-",
+            "the red lion",
+            "pub_1",
+            "café",
+            &too_long,
+        ] {
+            assert!(
+                matches!(check_slug(slug), Err(MyError::InvalidInput(_))),
+                "{:?} was accepted",
+                slug
+            );
+        }
+    }
+}
@@ -1,18 +1,75 @@
-use crate::error::Result;
+use crate::auth;
+use crate::codes;
+use crate::error::{MyError, Result};
+use crate::registry;
+use crate::signaling::{validate_sdp, RelayScope, DIRECT_MESSAGE_SCOPE};
 use crate::types::{
-    Client, Command, DbConnection, Person, Pub, PubTable, PubWithPeople, Response, TableWithPeople,
+    Account, AuditAction, AuditEvent, Block, ChatMessage, ChatScope, Client, Command, DbConnection,
+    DirectMessage, Notification, NotificationKind, Person, Pub, PubRole, PubTable, PubVisibility,
+    PubWithPeople, Receipt, Response, Secret, TableAccess, TableWithPeople,
 };
-use dashmap::DashMap;
+use chrono::NaiveDateTime;
+use dashmap::{DashMap, DashSet};
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
 use lazy_static::lazy_static;
 use log::{debug, info, warn};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use serde_json::json;
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use uuid::Uuid;
 use warp::ws::{Message, WebSocket};
 
+const MAX_REASON_LENGTH: usize = 500;
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+const MAX_TOPIC_LENGTH: usize = 200;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LENGTH: usize = 30;
+const MAX_CHAT_LENGTH: usize = 2000;
+const MAX_EMOJI_LENGTH: usize = 32;
+const DEFAULT_CHAT_HISTORY_LIMIT: i64 = 50;
+const MAX_CHAT_HISTORY_LIMIT: i64 = 200;
+const MAX_SEARCH_LENGTH: usize = 200;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+const DEFAULT_NOTIFICATION_LIMIT: i64 = 50;
+const MAX_NOTIFICATION_LIMIT: i64 = 200;
+/// Past this, the rest of a message's `@name`s are ignored
+const MAX_MENTIONS: usize = 20;
+/// How much of a message goes in a mention's notification, in characters
+const MENTION_EXCERPT_LENGTH: usize = 100;
+/// Upper limit on `max_seats`, well past where a mesh video call stops working
+const MAX_SEATS: i32 = 50;
+const DEFAULT_AUDIT_LOG_LIMIT: i64 = 50;
+const MAX_AUDIT_LOG_LIMIT: i64 = 200;
+/// How long pub invites last, in seconds
+const DEFAULT_INVITE_TTL: u64 = 7 * 24 * 60 * 60;
+const MAX_INVITE_TTL: u64 = 30 * 24 * 60 * 60;
+/// Longer bans than this should just be left without a duration
+const MAX_BAN_DURATION: u64 = 365 * 24 * 60 * 60;
+/// Wrong join codes allowed per person in `CODE_FAILURE_WINDOW`, so codes
+/// can't be guessed
+const MAX_CODE_FAILURES: u32 = 10;
+const CODE_FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
 lazy_static! {
-    static ref ADDRS: DashMap<Uuid, UnboundedSender<Message>> = DashMap::new();
+    /// Sessions subscribed to the lobby, as (person, session) pairs
+    static ref LOBBY: DashSet<(Uuid, Uuid)> = DashSet::new();
+    /// People who've disconnected but are still holding their seat, along with
+    /// a token for the disconnect that'll free it up
+    static ref PENDING_LEAVES: DashMap<Uuid, Uuid> = DashMap::new();
+    /// Wrong join codes per person, and when the first of them was tried
+    static ref CODE_FAILURES: DashMap<Uuid, (u32, Instant)> = DashMap::new();
+    /// How long someone keeps their seat after disconnecting, set via
+    /// `DISCONNECT_GRACE_SECONDS`
+    static ref DISCONNECT_GRACE: Duration = Duration::from_secs(
+        env::var("DISCONNECT_GRACE_SECONDS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(30)
+    );
 }
 
 impl Client {
@@ -35,7 +92,21 @@ impl Client {
             }
         });
 
-        ADDRS.insert(self.id, tx);
+        if PENDING_LEAVES.remove(&self.id).is_some() {
+            info!("Reconnected within grace period: {}", self.id);
+        }
+        registry::register(self.id, self.session_id, tx);
+        if let Err(e) = self
+            .send_response(&Response::Session {
+                session_id: self.session_id,
+            })
+            .await
+        {
+            warn!("Failed to send session to {}: {}", self.id, e);
+        }
+        if let Err(e) = self.deliver_direct_messages().await {
+            warn!("Failed to deliver direct messages to {}: {}", self.id, e);
+        }
 
         while let Some(result) = user_ws_rx.next().await {
             let msg = match result {
@@ -48,196 +119,2007 @@ impl Client {
             self.handle_msg(msg).await;
         }
 
-        info!("Disconnected: {}", self.id);
+        info!("Disconnected: {} (session {})", self.id, self.session_id);
+        self.disconnected();
+    }
+
+    /// Deregisters this session, and once the person has no sessions left,
+    /// gives them a grace period to reconnect before they leave the pub
+    fn disconnected(&self) {
+        LOBBY.remove(&(self.id, self.session_id));
+        if !registry::deregister(self.id, self.session_id) {
+            // Still got other tabs or devices open
+            return;
+        }
 
-        // user_ws_rx stream will keep processing as long as the user stays
-        // connected. Once they disconnect, then...
-        // user_disconnected(my_id, &users).await;
+        let token = Uuid::new_v4();
+        PENDING_LEAVES.insert(self.id, token);
+        let client = self.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(*DISCONNECT_GRACE).await;
+            if PENDING_LEAVES
+                .remove_if(&client.id, |_, pending| *pending == token)
+                .is_none()
+            {
+                return;
+            }
+            info!("Grace period expired for {}, leaving pub", client.id);
+            let res = match client.pool.get().await {
+                Ok(mut conn) => match leave_table(&mut conn, client.id).await {
+                    Ok(()) => leave_pub(&mut conn, client.id).await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = res {
+                warn!("Failed to remove {} from their pub: {}", client.id, e);
+            }
+        });
     }
 
-    async fn send_text<S: Into<String> + std::fmt::Display>(&self, msg: S) {
-        if let Some(tx) = ADDRS.get(&self.id) {
-            debug!("send_text: {}", msg);
-            tx.send(Message::text(msg)).unwrap();
+    /// Hands over anything sent while the person had no sessions open
+    async fn deliver_direct_messages(&self) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let pending = DirectMessage::undelivered(&mut conn, self.id).await?;
+        let up_to = match pending.last() {
+            Some(last) => last.id,
+            None => return Ok(()),
         };
+        for data in pending {
+            self.send_response(&Response::DirectMessage { data })
+                .await?;
+        }
+        for (sender_id, up_to, at) in
+            DirectMessage::mark(&mut conn, self.id, None, up_to, Receipt::Delivered).await?
+        {
+            send_receipt(sender_id, self.id, up_to, Receipt::Delivered, at)?;
+        }
+        Ok(())
     }
 
-    async fn leave_pub<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
-        Person::leave_pub(conn, self.id).await
+    /// Replies go only to the session that asked
+    async fn send_text<S: Into<String>>(&self, msg: S) {
+        registry::send_to_session(self.id, self.session_id, &msg.into());
     }
 
-    async fn leave_table<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
-        Person::leave_table(conn, self.id).await
+    async fn send_response(&self, response: &Response) -> Result<()> {
+        self.send_text(serde_json::to_string(response)?).await;
+        Ok(())
     }
 
-    async fn return_self<'a>(&self, conn: &mut DbConnection<'a>) {
-        self.send_text(
-            serde_json::to_string(&Response::Person {
-                data: Person::load_from_db(conn, self.id).await.unwrap(),
-            })
-            .unwrap(),
+    async fn send_error(&self, error: MyError, command: Option<String>) {
+        let code = error.code();
+        warn!(
+            "Error for {} running {}: {}",
+            self.id,
+            command.as_deref().unwrap_or("<unknown>"),
+            error
+        );
+        let response = Response::Error {
+            code,
+            message: error.public_message(),
+            command,
+        };
+        if let Err(e) = self.send_response(&response).await {
+            warn!("Failed to send error to {}: {}", self.id, e);
+        }
+    }
+
+    async fn return_self<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
+        self.send_response(&Response::Person {
+            data: Person::load_from_db(conn, self.id).await?,
+        })
+        .await
+    }
+
+    fn require_account(&self) -> Result<Uuid> {
+        self.account_id.ok_or_else(|| {
+            MyError::Forbidden("You need to be logged in to an account to do that".to_string())
+        })
+    }
+
+    async fn check_in_pub<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        let me = Person::load_from_db(conn, self.id).await?;
+        if me.pub_id != Some(pub_id) {
+            return Err(MyError::Forbidden(format!(
+                "You need to be in pub {pub_id} to do that"
+            )));
+        }
+        Ok(())
+    }
+
+    async fn require_role<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        needed: PubRole,
+    ) -> Result<PubRole> {
+        let role = Pub::get_role(conn, pub_id, self.id).await?;
+        if role < needed {
+            return Err(MyError::Forbidden(format!(
+                "You need to be {needed:?} or above in pub {pub_id} to do that"
+            )));
+        }
+        Ok(role)
+    }
+
+    /// Checks we're allowed to moderate `user_id` in our current pub, which
+    /// needs at least the `needed` role and to outrank them
+    async fn check_moderate<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        user_id: Uuid,
+        needed: PubRole,
+    ) -> Result<Uuid> {
+        let pub_id = Person::load_from_db(conn, self.id)
+            .await?
+            .pub_id
+            .ok_or_else(|| MyError::Forbidden("You need to be in a pub to do that".to_string()))?;
+        if user_id == self.id {
+            return Err(MyError::InvalidInput(
+                "You can't do that to yourself".to_string(),
+            ));
+        }
+        let role = self.require_role(conn, pub_id, needed).await?;
+        let their_role = Pub::get_role(conn, pub_id, user_id).await?;
+        if their_role >= role {
+            return Err(MyError::Forbidden(format!(
+                "You can't do that to someone who's {their_role:?}"
+            )));
+        }
+        Ok(pub_id)
+    }
+
+    /// Checks we can sit at a table, either because it's open to anyone or
+    /// we've got an invite or the right password
+    async fn check_table_access<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        password: Option<Secret>,
+    ) -> Result<()> {
+        let (access, password_hash) = PubTable::get_access(conn, table_id).await?;
+        if access == TableAccess::Open
+            || Person::load_from_db(conn, self.id).await?.table_id == Some(table_id)
+            || PubTable::is_invited(conn, table_id, self.id).await?
+        {
+            return Ok(());
+        }
+        match (access, password, password_hash) {
+            (TableAccess::Password, Some(Secret(password)), Some(password_hash)) => {
+                if auth::verify_password(password, password_hash).await? {
+                    Ok(())
+                } else {
+                    Err(MyError::Forbidden(
+                        "Wrong password for that table".to_string(),
+                    ))
+                }
+            }
+            (TableAccess::Password, _, _) => Err(MyError::Forbidden(
+                "That table needs a password".to_string(),
+            )),
+            (TableAccess::Knock, _, _) => Err(MyError::Forbidden(
+                "Knock to ask to join that table".to_string(),
+            )),
+            _ => Err(MyError::Forbidden("That table is invite only".to_string())),
+        }
+    }
+
+    /// Checks we can let people into a table, which anyone sat there or any
+    /// staff can do. Returns the table's pub.
+    async fn check_host<'a>(&self, conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Uuid> {
+        let pub_id = PubTable::get_pub_id(conn, table_id).await?;
+        self.check_in_pub(conn, pub_id).await?;
+        if Person::load_from_db(conn, self.id).await?.table_id != Some(table_id) {
+            self.require_role(conn, pub_id, PubRole::BarStaff).await?;
+        }
+        Ok(pub_id)
+    }
+
+    async fn audit<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        action: AuditAction,
+        target_id: Uuid,
+        reason: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        AuditEvent::record(
+            conn,
+            pub_id,
+            self.id,
+            action,
+            Some(target_id),
+            reason.as_deref(),
+            details,
         )
-        .await;
+        .await
     }
 
-    async fn send_tables<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) {
-        self.send_text(
-            serde_json::to_string(&Response::Tables {
-                list: PubTable::get_tables(conn, pub_id).await.unwrap(),
-            })
-            .unwrap(),
+    /// Records a moderation action done to someone, and lets them know about it
+    async fn moderated<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        action: AuditAction,
+        target_id: Uuid,
+        reason: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        notify(
+            conn,
+            target_id,
+            Some(self.id),
+            NotificationKind::Moderation {
+                pub_id,
+                action,
+                reason: reason.clone(),
+            },
         )
-        .await;
+        .await?;
+        self.audit(conn, pub_id, action, target_id, reason, details)
+            .await
+    }
+
+    /// Notifies anyone `@name`d in a new chat message, apart from the author
+    async fn notify_mentions<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        message: &ChatMessage,
+    ) -> Result<()> {
+        let names = parse_mentions(&message.content);
+        if names.is_empty() {
+            return Ok(());
+        }
+        let blockers = Block::get_blockers(conn, self.id).await?;
+        let kind = NotificationKind::Mention {
+            pub_id: message.pub_id,
+            table_id: message.table_id,
+            message_id: message.id,
+            excerpt: message
+                .content
+                .chars()
+                .take(MENTION_EXCERPT_LENGTH)
+                .collect(),
+        };
+        for person_id in
+            Person::find_mentioned(conn, message.pub_id, message.table_id, &names).await?
+        {
+            if person_id != self.id && !blockers.contains(&person_id) {
+                notify(conn, person_id, Some(self.id), kind.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_not_blocked<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        user_id: Uuid,
+    ) -> Result<()> {
+        if Block::is_between(conn, self.id, user_id).await? {
+            return Err(MyError::Forbidden(format!(
+                "You and {user_id} can't message each other"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Passes a message on to another connected person, as long as they're
+    /// within `scope` of us and neither of us has blocked the other
+    async fn relay<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        scope: RelayScope,
+        deliver: fn(Uuid, &str) -> bool,
+        user_id: Uuid,
+        response: &Response,
+    ) -> Result<()> {
+        let me = Person::load_from_db(conn, self.id).await?;
+        let recipient = Person::load_from_db(conn, user_id).await?;
+        scope.check(&me, &recipient)?;
+        self.check_not_blocked(conn, user_id).await?;
+        if !deliver(user_id, &serde_json::to_string(response)?) {
+            return Err(MyError::NotFound(format!("{user_id} is not connected")));
+        }
+        Ok(())
+    }
+
+    /// Pub chat is for everyone in the pub, and table chat for everyone sat
+    /// there. Returns the pub, and the table if it's table chat.
+    async fn check_chat_scope<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        scope: ChatScope,
+    ) -> Result<(Uuid, Option<Uuid>)> {
+        match scope {
+            ChatScope::Pub { pub_id } => {
+                self.check_in_pub(conn, pub_id).await?;
+                Ok((pub_id, None))
+            }
+            ChatScope::Table { table_id } => {
+                let me = Person::load_from_db(conn, self.id).await?;
+                match me.pub_id {
+                    Some(pub_id) if me.table_id == Some(table_id) => Ok((pub_id, Some(table_id))),
+                    _ => Err(MyError::Forbidden(format!(
+                        "You need to be sat at table {table_id} to do that"
+                    ))),
+                }
+            }
+        }
+    }
+
+    async fn check_not_muted<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        if Pub::is_muted(conn, pub_id, self.id).await? {
+            return Err(MyError::Forbidden(
+                "You've been muted in this pub".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sends the message as it is now to everyone who can see it
+    async fn chat_message_updated<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+    ) -> Result<()> {
+        let data = ChatMessage::load(conn, message_id).await?;
+        broadcast_chat(
+            conn,
+            &data,
+            &Response::ChatMessageUpdated { data: data.clone() },
+        )
+        .await
+    }
+
+    /// Looks up a join code, giving up for a while after too many wrong ones
+    async fn find_by_code<'a>(&self, conn: &mut DbConnection<'a>, code: &str) -> Result<Uuid> {
+        CODE_FAILURES.retain(|_, (_, first)| first.elapsed() < CODE_FAILURE_WINDOW);
+        if CODE_FAILURES
+            .get(&self.id)
+            .map_or(false, |failures| failures.0 >= MAX_CODE_FAILURES)
+        {
+            return Err(MyError::Forbidden(
+                "Too many wrong codes, try again later".to_string(),
+            ));
+        }
+        let res = Pub::find_by_code(conn, &codes::normalise_code(code)).await;
+        if let Err(MyError::NotFound(_)) = res {
+            CODE_FAILURES
+                .entry(self.id)
+                .or_insert((0, Instant::now()))
+                .0 += 1;
+        }
+        res
+    }
+
+    /// Private pubs need an invite, unless we're staff there
+    async fn check_not_private<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        let visibility = Pub::load_from_db(conn, pub_id).await?.visibility;
+        if visibility == PubVisibility::Private
+            && Pub::get_role(conn, pub_id, self.id).await? < PubRole::BarStaff
+        {
+            return Err(MyError::Forbidden(format!(
+                "You need an invite to get into pub {pub_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Moves us into a pub we're allowed into, out of wherever we were before
+    async fn join_pub<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        if Pub::is_banned(conn, pub_id, self.id).await? {
+            return Err(MyError::Forbidden(format!(
+                "You've been banned from pub {pub_id}"
+            )));
+        }
+        // Only allowed to be in one pub
+        leave_table(conn, self.id).await?;
+        leave_pub(conn, self.id).await?;
+        Person::set_pub(conn, self.id, pub_id).await?;
+        notify_lobby_occupancy(conn, pub_id).await?;
+        let me = Person::load_from_db(conn, self.id).await?;
+        broadcast_to_pub(
+            conn,
+            pub_id,
+            &Response::PersonJoinedPub { pub_id, data: me },
+        )
+        .await?;
+        self.send_response(&Response::Pub {
+            data: Pub::get_pub(conn, pub_id).await?,
+        })
+        .await?;
+        self.return_self(conn).await?;
+        self.send_tables(conn, pub_id).await
+    }
+
+    async fn send_invites<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        self.send_response(&Response::PubInvites {
+            pub_id,
+            list: Pub::get_invites(conn, pub_id).await?,
+        })
+        .await
+    }
+
+    /// Goes to all our sessions, so they all stop showing the people we've
+    /// blocked
+    async fn send_blocks<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
+        send_to_person(
+            self.id,
+            &Response::Blocks {
+                list: Block::list(conn, self.id).await?,
+            },
+        )
+    }
+
+    async fn send_tables<'a>(&self, conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+        self.send_response(&Response::Tables {
+            list: PubTable::get_tables(conn, pub_id).await?,
+        })
+        .await
     }
 
     async fn handle_msg(&self, msg: Message) {
-        if msg.is_ping() {
-            println!("msg: {msg:?}");
-        } else if msg.is_text() {
+        if msg.is_text() {
             let text = msg.to_str().unwrap();
+            // Only ever log the kind, as commands can carry passwords, invite
+            // tokens and private messages
+            let kind = command_kind(text);
             match serde_json::from_str::<Command>(text) {
                 Ok(cmd) => {
-                    println!("command: {cmd:?}");
-                    let mut conn = self.pool.get().await.unwrap();
-                    match cmd {
-                        Command::ListPubs => {
-                            let pubs = serde_json::to_string(&Response::Pubs {
-                                list: Pub::get_pubs(&mut conn).await.unwrap(),
-                            })
-                            .unwrap();
-                            self.send_text(pubs).await;
-                        }
-                        Command::CreatePub { name } => {
-                            self.leave_table(&mut conn).await.unwrap();
-                            self.leave_pub(&mut conn).await.unwrap();
-                            let pub_id = Uuid::new_v4();
-                            let new_pub = Pub {
-                                id: pub_id,
-                                name: name.clone(),
-                            };
-                            new_pub.add_pub(&mut conn).await.unwrap();
-                            Person::set_pub(&mut conn, self.id, pub_id).await.unwrap();
-                            self.send_text(
-                                serde_json::to_string(&Response::CreatePub {
-                                    data: PubWithPeople {
-                                        id: pub_id,
-                                        name,
-                                        persons: vec![self.id],
-                                    },
-                                })
-                                .unwrap(),
-                            )
-                            .await;
-                            self.return_self(&mut conn).await;
-                        }
-                        Command::DeletePub { pub_id } => {
-                            Pub::delete_pub(&mut conn, pub_id).await.unwrap();
-                            let pubs = serde_json::to_string(&Response::Pubs {
-                                list: Pub::get_pubs(&mut conn).await.unwrap(),
-                            })
-                            .unwrap();
-                            self.send_text(pubs).await;
-                        }
-                        Command::JoinPub { pub_id } => {
-                            // Only allowed to be in one pub
-                            self.leave_table(&mut conn).await.unwrap();
-                            self.leave_pub(&mut conn).await.unwrap();
-                            Person::set_pub(&mut conn, self.id, pub_id).await.unwrap();
-                            self.return_self(&mut conn).await;
-                            self.send_tables(&mut conn, pub_id).await;
-                        }
-                        Command::CreateTable { pub_id, name } => {
-                            self.leave_table(&mut conn).await.unwrap();
-                            let table_id = Uuid::new_v4();
-                            let new_table = PubTable {
-                                id: table_id,
-                                pub_id,
-                                name: name.clone(),
-                            };
-                            new_table.add_table(&mut conn).await.unwrap();
-                            Person::set_table(&mut conn, self.id, table_id)
-                                .await
-                                .unwrap();
-                            self.send_text(
-                                serde_json::to_string(&Response::CreateTable {
-                                    data: TableWithPeople {
-                                        id: table_id,
-                                        pub_id,
-                                        name,
-                                        persons: vec![self.id],
-                                    },
-                                })
-                                .unwrap(),
-                            )
-                            .await;
-                            self.return_self(&mut conn).await;
-                        }
-                        Command::JoinTable { table_id } => {
-                            // Only allowed to be in one pub
-                            self.leave_table(&mut conn).await.unwrap();
-                            Person::set_table(&mut conn, self.id, table_id)
-                                .await
-                                .unwrap();
-
-                            self.return_self(&mut conn).await;
-                        }
-                        Command::LeavePub | Command::LeaveTable => {
-                            self.leave_table(&mut conn).await.unwrap();
-                            if cmd == Command::LeavePub {
-                                self.leave_pub(&mut conn).await.unwrap();
-                            }
-                            self.return_self(&mut conn).await;
-                        }
-                        Command::ListTables { pub_id } => {
-                            self.send_tables(&mut conn, pub_id).await;
-                        }
-                        Command::Send { user_id, content } => {
-                            match ADDRS.get(&user_id) {
-                                Some(addr) => {
-                                    addr.send(Message::text(
-                                        serde_json::to_string(&Response::Data {
-                                            author: self.id,
-                                            content,
-                                        })
-                                        .unwrap(),
-                                    ))
-                                    .unwrap();
-                                }
-                                None => {
-                                    println!("Can't send to {user_id}. Available addrs");
-                                }
-                            };
-                        }
-                        Command::SetName { name } => {
-                            Person::set_name(&mut conn, self.id, name).await.unwrap();
-                            self.return_self(&mut conn).await;
-                        }
-                        Command::GetPerson { user_id } => {
-                            let person = serde_json::to_string(&Response::Person {
-                                data: Person::load_from_db(&mut conn, user_id).await.unwrap(),
-                            })
-                            .unwrap();
-                            self.send_text(person).await;
-                        }
-                        Command::DeleteTable { table_id } => {
-                            let pub_id = PubTable::delete_table(&mut conn, table_id).await.unwrap();
-                            self.send_tables(&mut conn, pub_id).await;
-                        }
-                        Command::Ping => {
-                            Person::update_last(&mut conn, self.id).await.unwrap();
-                            self.send_text(serde_json::to_string(&Response::Pong).unwrap())
-                                .await;
-                        }
+                    debug!(
+                        "Command from {}: {}",
+                        self.id,
+                        kind.as_deref().unwrap_or("<unknown>")
+                    );
+                    if let Err(error) = self.handle_command(cmd).await {
+                        self.send_error(error, kind).await;
                     }
                 }
-                Err(_error) => {
-                    println!("Error parsing command: {text}");
+                Err(error) => {
+                    self.send_error(
+                        MyError::InvalidInput(format!("Can't parse command: {error}")),
+                        kind,
+                    )
+                    .await;
                 }
             }
         } else if msg.is_binary() {
-            println!("bin: {msg:?}");
-        } else {
-            println!("Something else: {msg:?}")
+            warn!("Ignoring binary message from {}", self.id);
+        }
+    }
+
+    async fn handle_command(&self, cmd: Command) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        match cmd {
+            Command::ListPubs => {
+                self.send_response(&Response::Pubs {
+                    list: Pub::get_pubs(&mut conn).await?,
+                })
+                .await?;
+            }
+            Command::SubscribeLobby => {
+                LOBBY.insert((self.id, self.session_id));
+                self.send_response(&Response::Pubs {
+                    list: Pub::get_pubs(&mut conn).await?,
+                })
+                .await?;
+            }
+            Command::UnsubscribeLobby => {
+                LOBBY.remove(&(self.id, self.session_id));
+            }
+            Command::CreatePub {
+                name,
+                visibility,
+                slug,
+            } => {
+                let name = check_name(&name)?;
+                let visibility = visibility.unwrap_or_default();
+                let slug = slug.as_deref().map(codes::check_slug).transpose()?;
+                let pub_id = Uuid::new_v4();
+                let mut new_pub = Pub {
+                    id: pub_id,
+                    name: name.clone(),
+                    owner_id: Some(self.id),
+                    visibility,
+                    code: codes::generate_code(),
+                    slug,
+                    description: None,
+                    tags: vec![],
+                };
+                // Before leaving, so we stay put if the slug's been taken
+                new_pub.add_pub(&mut conn).await?;
+                leave_table(&mut conn, self.id).await?;
+                leave_pub(&mut conn, self.id).await?;
+                Person::set_pub(&mut conn, self.id, pub_id).await?;
+                let data = PubWithPeople {
+                    id: pub_id,
+                    name,
+                    owner_id: Some(self.id),
+                    visibility,
+                    code: new_pub.code,
+                    slug: new_pub.slug,
+                    description: None,
+                    tags: vec![],
+                    persons: vec![self.id],
+                };
+                self.send_response(&Response::CreatePub { data: data.clone() })
+                    .await?;
+                if visibility == PubVisibility::Public {
+                    broadcast_to_lobby(&Response::PubCreated { data })?;
+                }
+                self.return_self(&mut conn).await?;
+            }
+            Command::DeletePub { pub_id, reason } => {
+                check_reason(&reason)?;
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                let visibility = Pub::load_from_db(&mut conn, pub_id).await?.visibility;
+                Pub::delete_pub(&mut conn, pub_id).await?;
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::DeletePub,
+                    pub_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                if visibility == PubVisibility::Public {
+                    broadcast_to_lobby(&Response::PubDeleted { pub_id })?;
+                }
+                self.send_response(&Response::Pubs {
+                    list: Pub::get_pubs(&mut conn).await?,
+                })
+                .await?;
+            }
+            Command::UpdatePub {
+                pub_id,
+                name,
+                description,
+                tags,
+            } => {
+                let name = name.as_deref().map(check_name).transpose()?;
+                check_length("Descriptions", &description, MAX_DESCRIPTION_LENGTH)?;
+                let tags = tags.map(check_tags).transpose()?;
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                let before = Pub::load_from_db(&mut conn, pub_id).await?;
+                Pub::update(
+                    &mut conn,
+                    pub_id,
+                    name.as_deref(),
+                    description.as_deref(),
+                    tags.as_deref(),
+                )
+                .await?;
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::UpdatePub,
+                    pub_id,
+                    None,
+                    json!({
+                        "previous_name": before.name,
+                        "name": name,
+                        "description": description,
+                        "tags": tags,
+                    }),
+                )
+                .await?;
+                let data = Pub::get_pub(&mut conn, pub_id).await?;
+                let update = Response::PubUpdated { data };
+                broadcast_to_pub(&mut conn, pub_id, &update).await?;
+                if before.visibility == PubVisibility::Public {
+                    broadcast_to_lobby(&update)?;
+                }
+            }
+            Command::ClaimPub { pub_id } => {
+                // Guests come and go, so only accounts can own pubs
+                self.require_account()?;
+                self.check_in_pub(&mut conn, pub_id).await?;
+                if !Pub::claim(&mut conn, pub_id, self.id).await? {
+                    return Err(MyError::Conflict(format!(
+                        "Pub {pub_id} already has an owner"
+                    )));
+                }
+                info!("{} claimed {}", self.id, pub_id);
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::ClaimPub,
+                    pub_id,
+                    None,
+                    json!({}),
+                )
+                .await?;
+                let data = Pub::get_pub(&mut conn, pub_id).await?;
+                let visibility = data.visibility;
+                let update = Response::PubUpdated { data };
+                broadcast_to_pub(&mut conn, pub_id, &update).await?;
+                if visibility == PubVisibility::Public {
+                    broadcast_to_lobby(&update)?;
+                }
+            }
+            Command::JoinPub { pub_id } => {
+                self.check_not_private(&mut conn, pub_id).await?;
+                self.join_pub(&mut conn, pub_id).await?;
+            }
+            Command::JoinPubByCode { code } => {
+                let pub_id = self.find_by_code(&mut conn, &code).await?;
+                self.check_not_private(&mut conn, pub_id).await?;
+                self.join_pub(&mut conn, pub_id).await?;
+            }
+            Command::JoinPubByInvite {
+                token: Secret(token),
+            } => {
+                let claims = auth::verify_invite_token(&token)?;
+                if !Pub::is_invite_valid(&mut conn, claims.pub_id, claims.invite_id).await? {
+                    return Err(MyError::Forbidden(
+                        "This invite has been revoked".to_string(),
+                    ));
+                }
+                self.join_pub(&mut conn, claims.pub_id).await?;
+            }
+            Command::CreatePubInvite { pub_id, ttl } => {
+                let ttl = ttl.unwrap_or(DEFAULT_INVITE_TTL);
+                if !(1..=MAX_INVITE_TTL).contains(&ttl) {
+                    return Err(MyError::InvalidInput(format!(
+                        "Invites can last for at most {MAX_INVITE_TTL} seconds"
+                    )));
+                }
+                self.require_role(&mut conn, pub_id, PubRole::BarStaff)
+                    .await?;
+                let invite = Pub::add_invite(&mut conn, pub_id, self.id, ttl).await?;
+                let token = auth::mint_invite_token(&invite)?;
+                self.send_response(&Response::PubInvite {
+                    data: invite,
+                    token,
+                })
+                .await?;
+            }
+            Command::ListPubInvites { pub_id } => {
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                self.send_invites(&mut conn, pub_id).await?;
+            }
+            Command::RevokePubInvite {
+                pub_id,
+                invite_id,
+                reason,
+            } => {
+                check_reason(&reason)?;
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                Pub::revoke_invite(&mut conn, pub_id, invite_id).await?;
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::RevokeInvite,
+                    invite_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                self.send_invites(&mut conn, pub_id).await?;
+            }
+            Command::CreateTable {
+                pub_id,
+                name,
+                max_seats,
+                access,
+                password,
+            } => {
+                let name = check_name(&name)?;
+                let access = access.unwrap_or_default();
+                let password_hash = match (access, password) {
+                    (TableAccess::Password, Some(Secret(password))) if !password.is_empty() => {
+                        Some(auth::hash_password(password).await?)
+                    }
+                    (TableAccess::Password, _) => {
+                        return Err(MyError::InvalidInput(
+                            "Password protected tables need a password".to_string(),
+                        ));
+                    }
+                    _ => None,
+                };
+                if let Some(max_seats) = max_seats {
+                    if !(1..=MAX_SEATS).contains(&max_seats) {
+                        return Err(MyError::InvalidInput(format!(
+                            "Tables can have between 1 and {MAX_SEATS} seats"
+                        )));
+                    }
+                }
+                self.check_in_pub(&mut conn, pub_id).await?;
+                leave_table(&mut conn, self.id).await?;
+                let table_id = Uuid::new_v4();
+                let new_table = PubTable {
+                    id: table_id,
+                    pub_id,
+                    name: name.clone(),
+                    max_seats,
+                    access,
+                    topic: None,
+                };
+                new_table
+                    .add_table(&mut conn, password_hash.as_deref())
+                    .await?;
+                Person::set_table(&mut conn, self.id, table_id).await?;
+                let data = TableWithPeople {
+                    id: table_id,
+                    pub_id,
+                    name,
+                    max_seats,
+                    access,
+                    topic: None,
+                    persons: vec![self.id],
+                };
+                self.send_response(&Response::CreateTable { data: data.clone() })
+                    .await?;
+                broadcast_to_pub(&mut conn, pub_id, &Response::TableCreated { data }).await?;
+                self.return_self(&mut conn).await?;
+            }
+            Command::JoinTable { table_id, password } => {
+                // Only allowed to be at one table, and only in our own pub
+                let pub_id = PubTable::get_pub_id(&mut conn, table_id).await?;
+                self.check_in_pub(&mut conn, pub_id).await?;
+                self.check_table_access(&mut conn, table_id, password)
+                    .await?;
+                seat_person(&mut conn, pub_id, table_id, self.id).await?;
+                self.return_self(&mut conn).await?;
+            }
+            Command::LeavePub | Command::LeaveTable => {
+                leave_table(&mut conn, self.id).await?;
+                if cmd == Command::LeavePub {
+                    leave_pub(&mut conn, self.id).await?;
+                }
+                self.return_self(&mut conn).await?;
+            }
+            Command::ListTables { pub_id } => {
+                // Who's sat where isn't for anyone outside the pub, as it
+                // might be a private one
+                self.check_in_pub(&mut conn, pub_id).await?;
+                self.send_tables(&mut conn, pub_id).await?;
+            }
+            Command::Send { user_id, content } => {
+                if let Some(pub_id) = Person::load_from_db(&mut conn, self.id).await?.pub_id {
+                    self.check_not_muted(&mut conn, pub_id).await?;
+                }
+                self.relay(
+                    &mut conn,
+                    *DIRECT_MESSAGE_SCOPE,
+                    registry::send_to_person,
+                    user_id,
+                    &Response::Data {
+                        author: self.id,
+                        content,
+                    },
+                )
+                .await?;
+            }
+            Command::Offer { user_id, sdp } => {
+                validate_sdp(&sdp)?;
+                info!("Offer from {} to {}", self.id, user_id);
+                self.relay(
+                    &mut conn,
+                    RelayScope::Table,
+                    registry::send_to_media,
+                    user_id,
+                    &Response::Offer {
+                        author: self.id,
+                        sdp,
+                    },
+                )
+                .await?;
+            }
+            Command::Answer { user_id, sdp } => {
+                validate_sdp(&sdp)?;
+                info!("Answer from {} to {}", self.id, user_id);
+                self.relay(
+                    &mut conn,
+                    RelayScope::Table,
+                    registry::send_to_media,
+                    user_id,
+                    &Response::Answer {
+                        author: self.id,
+                        sdp,
+                    },
+                )
+                .await?;
+            }
+            Command::IceCandidate { user_id, candidate } => {
+                candidate.validate()?;
+                debug!("ICE candidate from {} to {}", self.id, user_id);
+                self.relay(
+                    &mut conn,
+                    RelayScope::Table,
+                    registry::send_to_media,
+                    user_id,
+                    &Response::IceCandidate {
+                        author: self.id,
+                        candidate,
+                    },
+                )
+                .await?;
+            }
+            Command::Renegotiate { user_id } => {
+                info!("Renegotiate from {} to {}", self.id, user_id);
+                self.relay(
+                    &mut conn,
+                    RelayScope::Table,
+                    registry::send_to_media,
+                    user_id,
+                    &Response::Renegotiate { author: self.id },
+                )
+                .await?;
+            }
+            Command::Hangup { user_id } => {
+                info!("Hangup from {} to {}", self.id, user_id);
+                // Hangups often arrive just after someone's left the table
+                self.relay(
+                    &mut conn,
+                    RelayScope::Pub,
+                    registry::send_to_media,
+                    user_id,
+                    &Response::Hangup { author: self.id },
+                )
+                .await?;
+            }
+            Command::SetName { name } => {
+                let name = check_name(&name)?;
+                if let Some(account_id) = self.account_id {
+                    Account::set_display_name(&mut conn, account_id, &name).await?;
+                }
+                Person::set_name(&mut conn, self.id, name).await?;
+                self.return_self(&mut conn).await?;
+            }
+            Command::GetAccount => {
+                let account_id = self.require_account()?;
+                self.send_response(&Response::Account {
+                    data: Account::load_from_db(&mut conn, account_id).await?,
+                })
+                .await?;
+            }
+            Command::SetPreferences { preferences } => {
+                let account_id = self.require_account()?;
+                if !preferences.is_object() {
+                    return Err(MyError::InvalidInput(
+                        "Preferences need to be a JSON object".to_string(),
+                    ));
+                }
+                Account::set_preferences(&mut conn, account_id, &preferences).await?;
+                self.send_response(&Response::Account {
+                    data: Account::load_from_db(&mut conn, account_id).await?,
+                })
+                .await?;
+            }
+            Command::GetPerson { user_id } => {
+                self.send_response(&Response::Person {
+                    data: Person::load_from_db(&mut conn, user_id).await?,
+                })
+                .await?;
+            }
+            Command::DeleteTable { table_id, reason } => {
+                check_reason(&reason)?;
+                let pub_id = PubTable::get_pub_id(&mut conn, table_id).await?;
+                self.require_role(&mut conn, pub_id, PubRole::BarStaff)
+                    .await?;
+                PubTable::delete_table(&mut conn, table_id).await?;
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::DeleteTable,
+                    table_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                broadcast_to_pub(
+                    &mut conn,
+                    pub_id,
+                    &Response::TableDeleted { pub_id, table_id },
+                )
+                .await?;
+                self.send_tables(&mut conn, pub_id).await?;
+            }
+            Command::UpdateTable {
+                table_id,
+                name,
+                topic,
+            } => {
+                let name = name.as_deref().map(check_name).transpose()?;
+                check_length("Topics", &topic, MAX_TOPIC_LENGTH)?;
+                let pub_id = self.check_host(&mut conn, table_id).await?;
+                let before = PubTable::get_table(&mut conn, table_id).await?;
+                PubTable::update(&mut conn, table_id, name.as_deref(), topic.as_deref()).await?;
+                self.audit(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::UpdateTable,
+                    table_id,
+                    None,
+                    json!({
+                        "previous_name": before.name,
+                        "name": name,
+                        "topic": topic,
+                    }),
+                )
+                .await?;
+                let data = PubTable::get_table(&mut conn, table_id).await?;
+                broadcast_to_pub(&mut conn, pub_id, &Response::TableUpdated { data }).await?;
+            }
+            Command::ClaimMedia => {
+                if let Some(previous) = registry::claim_media(self.id, self.session_id) {
+                    info!(
+                        "Session {} took media over from {} for {}",
+                        self.session_id, previous, self.id
+                    );
+                    registry::send_to_session(
+                        self.id,
+                        previous,
+                        &serde_json::to_string(&Response::SessionReplaced {
+                            session_id: self.session_id,
+                        })?,
+                    );
+                }
+            }
+            Command::ListRoles { pub_id } => {
+                self.check_in_pub(&mut conn, pub_id).await?;
+                self.send_response(&Response::Roles {
+                    pub_id,
+                    list: Pub::get_roles(&mut conn, pub_id).await?,
+                })
+                .await?;
+            }
+            Command::SetRole {
+                pub_id,
+                user_id,
+                role,
+            } => {
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                let pub_info = Pub::load_from_db(&mut conn, pub_id).await?;
+                if pub_info.owner_id == Some(user_id) {
+                    return Err(MyError::Forbidden(
+                        "The owner's role can't be changed".to_string(),
+                    ));
+                }
+                Pub::set_role(&mut conn, pub_id, user_id, role).await?;
+                info!("{} made {} {:?} in {}", self.id, user_id, role, pub_id);
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::SetRole,
+                    user_id,
+                    None,
+                    json!({ "role": role }),
+                )
+                .await?;
+                broadcast_to_pub(
+                    &mut conn,
+                    pub_id,
+                    &Response::RoleChanged {
+                        pub_id,
+                        person_id: user_id,
+                        role,
+                    },
+                )
+                .await?;
+            }
+            Command::Kick { user_id, reason } => {
+                check_reason(&reason)?;
+                let pub_id = self
+                    .check_moderate(&mut conn, user_id, PubRole::BarStaff)
+                    .await?;
+                let notice = Response::Kicked {
+                    pub_id,
+                    by: self.id,
+                };
+                if !kick(&mut conn, pub_id, user_id, &notice).await? {
+                    return Err(MyError::NotFound(format!(
+                        "{user_id} isn't in pub {pub_id}"
+                    )));
+                }
+                info!("{} kicked {} from {}", self.id, user_id, pub_id);
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::Kick,
+                    user_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+            }
+            Command::Ban {
+                user_id,
+                duration,
+                reason,
+            } => {
+                check_reason(&reason)?;
+                if duration.map_or(false, |secs| !(1..=MAX_BAN_DURATION).contains(&secs)) {
+                    return Err(MyError::InvalidInput(format!(
+                        "Bans can last for at most {MAX_BAN_DURATION} seconds"
+                    )));
+                }
+                let pub_id = self
+                    .check_moderate(&mut conn, user_id, PubRole::Landlord)
+                    .await?;
+                let expires = Pub::ban(&mut conn, pub_id, user_id, self.id, duration).await?;
+                info!(
+                    "{} banned {} from {} until {:?}",
+                    self.id, user_id, pub_id, expires
+                );
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::Ban,
+                    user_id,
+                    reason,
+                    json!({ "duration": duration, "expires": expires }),
+                )
+                .await?;
+                let notice = Response::Banned {
+                    pub_id,
+                    by: self.id,
+                    expires,
+                };
+                // Anyone not in the pub still gets told
+                if !kick(&mut conn, pub_id, user_id, &notice).await? {
+                    send_to_person(user_id, &notice)?;
+                }
+            }
+            Command::Unban { user_id, reason } => {
+                check_reason(&reason)?;
+                let pub_id = self
+                    .check_moderate(&mut conn, user_id, PubRole::Landlord)
+                    .await?;
+                Pub::unban(&mut conn, pub_id, user_id).await?;
+                info!("{} unbanned {} from {}", self.id, user_id, pub_id);
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::Unban,
+                    user_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                send_to_person(user_id, &Response::Unbanned { pub_id })?;
+            }
+            Command::Mute { user_id, reason } => {
+                check_reason(&reason)?;
+                let pub_id = self
+                    .check_moderate(&mut conn, user_id, PubRole::BarStaff)
+                    .await?;
+                Pub::mute(&mut conn, pub_id, user_id, self.id).await?;
+                info!("{} muted {} in {}", self.id, user_id, pub_id);
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::Mute,
+                    user_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                send_to_person(
+                    user_id,
+                    &Response::Muted {
+                        pub_id,
+                        by: self.id,
+                    },
+                )?;
+            }
+            Command::Unmute { user_id, reason } => {
+                check_reason(&reason)?;
+                let pub_id = self
+                    .check_moderate(&mut conn, user_id, PubRole::BarStaff)
+                    .await?;
+                Pub::unmute(&mut conn, pub_id, user_id).await?;
+                info!("{} unmuted {} in {}", self.id, user_id, pub_id);
+                self.moderated(
+                    &mut conn,
+                    pub_id,
+                    AuditAction::Unmute,
+                    user_id,
+                    reason,
+                    json!({}),
+                )
+                .await?;
+                send_to_person(user_id, &Response::Unmuted { pub_id })?;
+            }
+            Command::ListAuditLog {
+                pub_id,
+                before,
+                limit,
+            } => {
+                self.require_role(&mut conn, pub_id, PubRole::Landlord)
+                    .await?;
+                let limit = limit
+                    .unwrap_or(DEFAULT_AUDIT_LOG_LIMIT)
+                    .clamp(1, MAX_AUDIT_LOG_LIMIT);
+                self.send_response(&Response::AuditLog {
+                    pub_id,
+                    list: AuditEvent::list(&mut conn, pub_id, before, limit).await?,
+                })
+                .await?;
+            }
+            Command::ChatMessage { scope, content } => {
+                let content = check_chat_content(&content)?;
+                let (pub_id, table_id) = self.check_chat_scope(&mut conn, scope).await?;
+                self.check_not_muted(&mut conn, pub_id).await?;
+                let data = ChatMessage::post(&mut conn, pub_id, table_id, self.id, content).await?;
+                broadcast_chat(
+                    &mut conn,
+                    &data,
+                    &Response::ChatMessage { data: data.clone() },
+                )
+                .await?;
+                self.notify_mentions(&mut conn, &data).await?;
+            }
+            Command::EditMessage {
+                message_id,
+                content,
+            } => {
+                let content = check_chat_content(&content)?;
+                let message = ChatMessage::load(&mut conn, message_id).await?;
+                if message.author_id != self.id {
+                    return Err(MyError::Forbidden(
+                        "You can only edit your own messages".to_string(),
+                    ));
+                }
+                self.check_not_muted(&mut conn, message.pub_id).await?;
+                ChatMessage::edit(&mut conn, message_id, self.id, content).await?;
+                self.chat_message_updated(&mut conn, message_id).await?;
+            }
+            Command::DeleteMessage { message_id, reason } => {
+                check_reason(&reason)?;
+                let message = ChatMessage::load(&mut conn, message_id).await?;
+                if message.author_id != self.id {
+                    let role = self
+                        .require_role(&mut conn, message.pub_id, PubRole::BarStaff)
+                        .await?;
+                    let their_role =
+                        Pub::get_role(&mut conn, message.pub_id, message.author_id).await?;
+                    if their_role >= role {
+                        return Err(MyError::Forbidden(format!(
+                            "You can't delete messages from someone who's {their_role:?}"
+                        )));
+                    }
+                }
+                ChatMessage::delete(&mut conn, message_id, self.id).await?;
+                if message.author_id != self.id {
+                    self.moderated(
+                        &mut conn,
+                        message.pub_id,
+                        AuditAction::DeleteMessage,
+                        message.author_id,
+                        reason,
+                        json!({ "message_id": message_id }),
+                    )
+                    .await?;
+                }
+                self.chat_message_updated(&mut conn, message_id).await?;
+            }
+            Command::React { message_id, emoji } => {
+                let emoji = emoji.trim();
+                if emoji.len() > MAX_EMOJI_LENGTH || !is_single_emoji(emoji) {
+                    return Err(MyError::InvalidInput(format!(
+                        "Reactions need to be a single emoji, of at most {MAX_EMOJI_LENGTH} bytes"
+                    )));
+                }
+                let message = ChatMessage::load(&mut conn, message_id).await?;
+                self.check_chat_scope(&mut conn, chat_scope(&message))
+                    .await?;
+                if message.deleted {
+                    return Err(MyError::NotFound(format!("No such message {message_id}")));
+                }
+                ChatMessage::toggle_reaction(&mut conn, message_id, self.id, emoji).await?;
+                self.chat_message_updated(&mut conn, message_id).await?;
+            }
+            Command::ListMessageVersions { message_id } => {
+                let message = ChatMessage::load(&mut conn, message_id).await?;
+                self.require_role(&mut conn, message.pub_id, PubRole::BarStaff)
+                    .await?;
+                self.send_response(&Response::MessageVersions {
+                    message_id,
+                    list: ChatMessage::get_versions(&mut conn, message_id).await?,
+                })
+                .await?;
+            }
+            Command::ChatHistory {
+                scope,
+                before,
+                limit,
+            } => {
+                let (pub_id, table_id) = self.check_chat_scope(&mut conn, scope).await?;
+                let limit = limit
+                    .unwrap_or(DEFAULT_CHAT_HISTORY_LIMIT)
+                    .clamp(1, MAX_CHAT_HISTORY_LIMIT);
+                self.send_response(&Response::ChatHistory {
+                    scope,
+                    list: ChatMessage::history(&mut conn, self.id, pub_id, table_id, before, limit)
+                        .await?,
+                })
+                .await?;
+            }
+            Command::DirectMessage { user_id, content } => {
+                let content = check_chat_content(&content)?;
+                if user_id == self.id {
+                    return Err(MyError::InvalidInput(
+                        "You can't message yourself".to_string(),
+                    ));
+                }
+                self.check_not_blocked(&mut conn, user_id).await?;
+                if !DirectMessage::has_conversation(&mut conn, self.id, user_id).await? {
+                    let me = Person::load_from_db(&mut conn, self.id).await?;
+                    let recipient = Person::load_from_db(&mut conn, user_id).await?;
+                    DIRECT_MESSAGE_SCOPE.check(&me, &recipient)?;
+                }
+                let mut data = DirectMessage::post(&mut conn, self.id, user_id, content).await?;
+                let text = serde_json::to_string(&Response::DirectMessage { data: data.clone() })?;
+                if registry::send_to_person(user_id, &text) {
+                    data = DirectMessage::set_delivered(&mut conn, data.id).await?;
+                }
+                registry::send_to_person(
+                    self.id,
+                    &serde_json::to_string(&Response::DirectMessage { data })?,
+                );
+            }
+            Command::Inbox => {
+                let list = DirectMessage::inbox(&mut conn, self.id).await?;
+                self.send_response(&Response::Inbox {
+                    unread: list.iter().map(|conversation| conversation.unread).sum(),
+                    list,
+                })
+                .await?;
+            }
+            Command::DirectMessageHistory {
+                user_id,
+                before,
+                limit,
+            } => {
+                let limit = limit
+                    .unwrap_or(DEFAULT_CHAT_HISTORY_LIMIT)
+                    .clamp(1, MAX_CHAT_HISTORY_LIMIT);
+                self.send_response(&Response::DirectMessageHistory {
+                    user_id,
+                    list: DirectMessage::history(&mut conn, self.id, user_id, before, limit)
+                        .await?,
+                })
+                .await?;
+            }
+            Command::MarkDirectMessagesRead { user_id, up_to } => {
+                for (sender_id, up_to, at) in
+                    DirectMessage::mark(&mut conn, self.id, Some(user_id), up_to, Receipt::Read)
+                        .await?
+                {
+                    send_receipt(sender_id, self.id, up_to, Receipt::Read, at)?;
+                }
+            }
+            Command::SearchMessages {
+                pub_id,
+                query,
+                limit,
+            } => {
+                self.check_in_pub(&mut conn, pub_id).await?;
+                let query = query.trim().to_string();
+                if query.is_empty() {
+                    return Err(MyError::InvalidInput("Search for something".to_string()));
+                }
+                check_length("Searches", &Some(query.clone()), MAX_SEARCH_LENGTH)?;
+                let limit = limit
+                    .unwrap_or(DEFAULT_SEARCH_LIMIT)
+                    .clamp(1, MAX_SEARCH_LIMIT);
+                let my_table_id = Person::load_from_db(&mut conn, self.id).await?.table_id;
+                let list =
+                    ChatMessage::search(&mut conn, self.id, pub_id, my_table_id, &query, limit)
+                        .await?;
+                self.send_response(&Response::SearchResults {
+                    pub_id,
+                    query,
+                    list,
+                })
+                .await?;
+            }
+            Command::QueueForTable { table_id, password } => {
+                let pub_id = PubTable::get_pub_id(&mut conn, table_id).await?;
+                self.check_in_pub(&mut conn, pub_id).await?;
+                self.check_table_access(&mut conn, table_id, password)
+                    .await?;
+                if Person::load_from_db(&mut conn, self.id).await?.table_id == Some(table_id) {
+                    return Err(MyError::InvalidInput(
+                        "You're already at that table".to_string(),
+                    ));
+                }
+                if Block::is_kept_off_table(&mut conn, table_id, self.id).await? {
+                    return Err(MyError::Forbidden(format!(
+                        "You can't sit at table {table_id}"
+                    )));
+                }
+                let previous = PubTable::queue_person(&mut conn, table_id, self.id).await?;
+                if let Some(previous) = previous.filter(|previous| *previous != table_id) {
+                    self.send_response(&Response::LeftQueue { table_id: previous })
+                        .await?;
+                    notify_queue(&mut conn, previous).await?;
+                }
+                // Straight in if there's room
+                seat_from_queue(&mut conn, table_id).await?;
+                notify_queue(&mut conn, table_id).await?;
+            }
+            Command::LeaveQueue => {
+                let table_id = PubTable::unqueue_person(&mut conn, self.id)
+                    .await?
+                    .ok_or_else(|| {
+                        MyError::NotFound("You're not queueing for a table".to_string())
+                    })?;
+                self.send_response(&Response::LeftQueue { table_id })
+                    .await?;
+                notify_queue(&mut conn, table_id).await?;
+            }
+            Command::InviteToTable { table_id, user_id } => {
+                let pub_id = self.check_host(&mut conn, table_id).await?;
+                if Person::load_from_db(&mut conn, user_id).await?.pub_id != Some(pub_id) {
+                    return Err(MyError::NotFound(format!(
+                        "{user_id} isn't in pub {pub_id}"
+                    )));
+                }
+                PubTable::invite(&mut conn, table_id, user_id, self.id).await?;
+                send_to_person(
+                    user_id,
+                    &Response::TableInvite {
+                        table_id,
+                        by: self.id,
+                    },
+                )?;
+                notify(
+                    &mut conn,
+                    user_id,
+                    Some(self.id),
+                    NotificationKind::TableInvite { pub_id, table_id },
+                )
+                .await?;
+            }
+            Command::KnockTable { table_id } => {
+                let pub_id = PubTable::get_pub_id(&mut conn, table_id).await?;
+                self.check_in_pub(&mut conn, pub_id).await?;
+                let (access, _) = PubTable::get_access(&mut conn, table_id).await?;
+                if access != TableAccess::Knock {
+                    return Err(MyError::InvalidInput(
+                        "That table doesn't take knocks".to_string(),
+                    ));
+                }
+                PubTable::knock(&mut conn, table_id, self.id).await?;
+                broadcast_to_table(
+                    &mut conn,
+                    table_id,
+                    &Response::Knock {
+                        table_id,
+                        person_id: self.id,
+                    },
+                )
+                .await?;
+            }
+            Command::Admit { table_id, user_id } => {
+                let pub_id = self.check_host(&mut conn, table_id).await?;
+                if !PubTable::is_knocking(&mut conn, table_id, user_id).await? {
+                    return Err(MyError::NotFound(format!(
+                        "{user_id} isn't knocking at table {table_id}"
+                    )));
+                }
+                if Person::load_from_db(&mut conn, user_id).await?.pub_id != Some(pub_id) {
+                    return Err(MyError::NotFound(format!(
+                        "{user_id} isn't in pub {pub_id}"
+                    )));
+                }
+                // Sitting down takes care of the knock, so it's kept if the
+                // table turns out to be full
+                seat_person(&mut conn, pub_id, table_id, user_id).await?;
+                send_to_person(
+                    user_id,
+                    &Response::Admitted {
+                        table_id,
+                        by: self.id,
+                    },
+                )?;
+                notify(
+                    &mut conn,
+                    user_id,
+                    Some(self.id),
+                    NotificationKind::Seated { pub_id, table_id },
+                )
+                .await?;
+                send_to_person(
+                    user_id,
+                    &Response::Person {
+                        data: Person::load_from_db(&mut conn, user_id).await?,
+                    },
+                )?;
+            }
+            Command::Deny { table_id, user_id } => {
+                self.check_host(&mut conn, table_id).await?;
+                if !PubTable::remove_knock(&mut conn, table_id, user_id).await? {
+                    return Err(MyError::NotFound(format!(
+                        "{user_id} isn't knocking at table {table_id}"
+                    )));
+                }
+                send_to_person(
+                    user_id,
+                    &Response::Denied {
+                        table_id,
+                        by: self.id,
+                    },
+                )?;
+            }
+            Command::ListNotifications { before, limit } => {
+                let limit = limit
+                    .unwrap_or(DEFAULT_NOTIFICATION_LIMIT)
+                    .clamp(1, MAX_NOTIFICATION_LIMIT);
+                self.send_response(&Response::Notifications {
+                    list: Notification::list(&mut conn, self.id, before, limit).await?,
+                    unread: Notification::count_unread(&mut conn, self.id).await?,
+                })
+                .await?;
+            }
+            Command::MarkRead { up_to } => {
+                Notification::mark_read(&mut conn, self.id, up_to).await?;
+                send_to_person(
+                    self.id,
+                    &Response::NotificationsRead {
+                        up_to,
+                        unread: Notification::count_unread(&mut conn, self.id).await?,
+                    },
+                )?;
+            }
+            Command::Block {
+                user_id,
+                keep_off_tables,
+            } => {
+                if user_id == self.id {
+                    return Err(MyError::InvalidInput(
+                        "You can't block yourself".to_string(),
+                    ));
+                }
+                Block::add(
+                    &mut conn,
+                    self.id,
+                    user_id,
+                    keep_off_tables.unwrap_or(false),
+                )
+                .await?;
+                info!("{} blocked {}", self.id, user_id);
+                self.send_blocks(&mut conn).await?;
+            }
+            Command::Unblock { user_id } => {
+                Block::remove(&mut conn, self.id, user_id).await?;
+                info!("{} unblocked {}", self.id, user_id);
+                self.send_blocks(&mut conn).await?;
+            }
+            Command::ListBlocks => {
+                self.send_response(&Response::Blocks {
+                    list: Block::list(&mut conn, self.id).await?,
+                })
+                .await?;
+            }
+            Command::Ping => {
+                Person::update_last(&mut conn, self.id).await?;
+                self.send_response(&Response::Pong).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_reason(reason: &Option<String>) -> Result<()> {
+    check_length("Reasons", reason, MAX_REASON_LENGTH)
+}
+
+/// Trims the message, and makes sure there's something left
+fn check_chat_content(content: &str) -> Result<&str> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(MyError::InvalidInput(
+            "Chat messages can't be empty".to_string(),
+        ));
+    }
+    check_length("Chat messages", &Some(content.to_string()), MAX_CHAT_LENGTH)?;
+    Ok(content)
+}
+
+/// Picks out who a chat message `@name`s, lowercased and without repeats.
+/// Names run for as long as there's letters, digits, `_` or `-`, and an `@`
+/// straight after a letter or digit is taken to be part of an email address.
+fn parse_mentions(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for (index, _) in content.match_indices('@') {
+        if content[..index]
+            .chars()
+            .next_back()
+            .map_or(false, |c| c.is_alphanumeric())
+        {
+            continue;
+        }
+        let name: String = content[index + 1..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+            .collect::<String>()
+            .to_lowercase();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+        if names.len() == MAX_MENTIONS {
+            break;
+        }
+    }
+    names
+}
+
+fn check_length(what: &str, text: &Option<String>, max: usize) -> Result<()> {
+    match text {
+        Some(text) if text.chars().count() > max => Err(MyError::InvalidInput(format!(
+            "{what} can be at most {max} characters"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Close enough to Unicode's emoji list without pulling in its tables: a
+/// pictograph, a keycap or a flag, optionally joined to more of them with
+/// zero width joiners
+fn is_single_emoji(text: &str) -> bool {
+    !text.is_empty() && text.split('\u{200d}').all(is_emoji_part)
+}
+
+fn is_emoji_part(part: &str) -> bool {
+    let mut chars = part.chars().peekable();
+    match chars.next() {
+        Some('0'..='9' | '#' | '*') => {
+            chars.next_if_eq(&'\u{fe0f}');
+            chars.next() == Some('\u{20e3}') && chars.next().is_none()
+        }
+        // Flags are pairs of regional indicators
+        Some('\u{1f1e6}'..='\u{1f1ff}') => {
+            matches!(chars.next(), Some('\u{1f1e6}'..='\u{1f1ff}')) && chars.next().is_none()
+        }
+        Some(
+            '\u{a9}'
+            | '\u{ae}'
+            | '\u{203c}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{21aa}'
+            | '\u{231a}'..='\u{23ff}'
+            | '\u{24c2}'
+            | '\u{25aa}'..='\u{25fe}'
+            | '\u{2600}'..='\u{27bf}'
+            | '\u{2934}'
+            | '\u{2935}'
+            | '\u{2b05}'..='\u{2b55}'
+            | '\u{3030}'
+            | '\u{303d}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1f000}'..='\u{1faff}',
+        ) => {
+            // Variation selectors, skin tones and tags only change how it looks
+            chars.all(|c| {
+                matches!(
+                    c,
+                    '\u{fe0f}' | '\u{1f3fb}'..='\u{1f3ff}' | '\u{e0020}'..='\u{e007f}'
+                )
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Trims the name, and makes sure there's something left
+pub(crate) fn check_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(MyError::InvalidInput("Names can't be empty".to_string()));
+    }
+    check_length("Names", &Some(name.to_string()), MAX_NAME_LENGTH)?;
+    Ok(name.to_string())
+}
+
+/// Tags are kept lowercase, without blanks or repeats
+fn check_tags(tags: Vec<String>) -> Result<Vec<String>> {
+    let mut checked: Vec<String> = vec![];
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || checked.contains(&tag) {
+            continue;
         }
+        check_length("Tags", &Some(tag.clone()), MAX_TAG_LENGTH)?;
+        checked.push(tag);
+    }
+    if checked.len() > MAX_TAGS {
+        return Err(MyError::InvalidInput(format!(
+            "Pubs can have at most {MAX_TAGS} tags"
+        )));
+    }
+    Ok(checked)
+}
+
+/// Takes someone out of their pub, and tells everyone who needs to know
+async fn leave_pub<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<()> {
+    let person = Person::load_from_db(conn, person_id).await?;
+    Person::leave_pub(conn, person_id).await?;
+    if let Some(table_id) = PubTable::unqueue_person(conn, person_id).await? {
+        notify_queue(conn, table_id).await?;
+    }
+    if let Some(pub_id) = person.pub_id {
+        notify_lobby_occupancy(conn, pub_id).await?;
+        broadcast_to_pub(conn, pub_id, &Response::PersonLeftPub { pub_id, person_id }).await?;
+    }
+    Ok(())
+}
+
+/// Removes people who've stopped pinging, telling their pubs and tables as if
+/// they'd left
+pub async fn cleanup_outdated<'a>(conn: &mut DbConnection<'a>) -> Result<()> {
+    for person_id in Person::find_outdated(conn).await? {
+        let res = match leave_table(conn, person_id).await {
+            Ok(()) => match leave_pub(conn, person_id).await {
+                Ok(()) => Person::delete(conn, person_id).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!("Failed to clean up {}: {}", person_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Takes someone away from their table, and tells the rest of the pub
+async fn leave_table<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<()> {
+    let person = Person::load_from_db(conn, person_id).await?;
+    Person::leave_table(conn, person_id).await?;
+    if let (Some(pub_id), Some(table_id)) = (person.pub_id, person.table_id) {
+        broadcast_to_pub(
+            conn,
+            pub_id,
+            &Response::PersonLeftTable {
+                table_id,
+                person_id,
+            },
+        )
+        .await?;
+        seat_from_queue(conn, table_id).await?;
+    }
+    Ok(())
+}
+
+/// Sits someone at a table, if there's room, and tells the pub
+async fn seat_person<'a>(
+    conn: &mut DbConnection<'a>,
+    pub_id: Uuid,
+    table_id: Uuid,
+    person_id: Uuid,
+) -> Result<()> {
+    let previous = Person::take_seat(conn, person_id, table_id).await?;
+    if let Some(previous) = previous.filter(|previous| *previous != table_id) {
+        broadcast_to_pub(
+            conn,
+            pub_id,
+            &Response::PersonLeftTable {
+                table_id: previous,
+                person_id,
+            },
+        )
+        .await?;
+        seat_from_queue(conn, previous).await?;
+    }
+    // Might have been queueing for it
+    notify_queue(conn, table_id).await?;
+    broadcast_to_pub(
+        conn,
+        pub_id,
+        &Response::PersonJoinedTable {
+            table_id,
+            person_id,
+        },
+    )
+    .await
+}
+
+/// Fills any free seats at a table from its queue. Seating someone can free up
+/// a seat at the table they were at, so that gets filled too.
+async fn seat_from_queue<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<()> {
+    let mut tables = vec![table_id];
+    while let Some(table_id) = tables.pop() {
+        let mut seated_anyone = false;
+        while let Some((person_id, previous)) = PubTable::seat_from_queue(conn, table_id).await? {
+            seated_anyone = true;
+            let pub_id = PubTable::get_pub_id(conn, table_id).await?;
+            info!("Seated {} at {} from the queue", person_id, table_id);
+            if let Some(previous) = previous {
+                broadcast_to_pub(
+                    conn,
+                    pub_id,
+                    &Response::PersonLeftTable {
+                        table_id: previous,
+                        person_id,
+                    },
+                )
+                .await?;
+                tables.push(previous);
+            }
+            broadcast_to_pub(
+                conn,
+                pub_id,
+                &Response::PersonJoinedTable {
+                    table_id,
+                    person_id,
+                },
+            )
+            .await?;
+            send_to_person(person_id, &Response::Seated { table_id })?;
+            notify(
+                conn,
+                person_id,
+                None,
+                NotificationKind::Seated { pub_id, table_id },
+            )
+            .await?;
+            send_to_person(
+                person_id,
+                &Response::Person {
+                    data: Person::load_from_db(conn, person_id).await?,
+                },
+            )?;
+        }
+        if seated_anyone {
+            notify_queue(conn, table_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Tells everyone in a table's queue where they are in it
+async fn notify_queue<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<()> {
+    let queue = PubTable::get_queue(conn, table_id).await?;
+    for (index, person_id) in queue.iter().enumerate() {
+        send_to_person(
+            *person_id,
+            &Response::QueuePosition {
+                table_id,
+                position: index + 1,
+                length: queue.len(),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Throws someone out of a pub, telling them why with `notice`. Returns false
+/// if they weren't in it.
+async fn kick<'a>(
+    conn: &mut DbConnection<'a>,
+    pub_id: Uuid,
+    person_id: Uuid,
+    notice: &Response,
+) -> Result<bool> {
+    let person = match Person::load_from_db(conn, person_id).await {
+        Ok(person) => person,
+        // Not been around for a while, so nothing to throw them out of
+        Err(MyError::NotFound(_)) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if person.pub_id != Some(pub_id) {
+        return Ok(false);
+    }
+    leave_table(conn, person_id).await?;
+    leave_pub(conn, person_id).await?;
+    send_to_person(person_id, notice)?;
+    send_to_person(
+        person_id,
+        &Response::Person {
+            data: Person::load_from_db(conn, person_id).await?,
+        },
+    )?;
+    Ok(true)
+}
+
+/// Keeps a notification for someone, and pushes it to them if they're online
+async fn notify<'a>(
+    conn: &mut DbConnection<'a>,
+    person_id: Uuid,
+    actor_id: Option<Uuid>,
+    kind: NotificationKind,
+) -> Result<()> {
+    let data = Notification::add(conn, person_id, actor_id, &kind).await?;
+    send_to_person(person_id, &Response::Notification { data })
+}
+
+/// Pushes a response to every session someone has open
+fn send_to_person(person_id: Uuid, response: &Response) -> Result<()> {
+    registry::send_to_person(person_id, &serde_json::to_string(response)?);
+    Ok(())
+}
+
+/// Pushes a response to every connected person currently in the pub
+async fn broadcast_to_pub<'a>(
+    conn: &mut DbConnection<'a>,
+    pub_id: Uuid,
+    response: &Response,
+) -> Result<()> {
+    let text = serde_json::to_string(response)?;
+    for person_id in Pub::get_person_ids(conn, pub_id).await? {
+        registry::send_to_person(person_id, &text);
+    }
+    Ok(())
+}
+
+async fn broadcast_to_table<'a>(
+    conn: &mut DbConnection<'a>,
+    table_id: Uuid,
+    response: &Response,
+) -> Result<()> {
+    let text = serde_json::to_string(response)?;
+    for person_id in PubTable::get_seated(conn, table_id).await? {
+        registry::send_to_person(person_id, &text);
+    }
+    Ok(())
+}
+
+/// Lets both ends of a conversation know how far the recipient has got
+fn send_receipt(
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    up_to: i64,
+    receipt: Receipt,
+    at: NaiveDateTime,
+) -> Result<()> {
+    let text = serde_json::to_string(&Response::DirectMessageReceipt {
+        sender_id,
+        recipient_id,
+        up_to,
+        receipt,
+        at,
+    })?;
+    registry::send_to_person(sender_id, &text);
+    registry::send_to_person(recipient_id, &text);
+    Ok(())
+}
+
+fn chat_scope(message: &ChatMessage) -> ChatScope {
+    match message.table_id {
+        Some(table_id) => ChatScope::Table { table_id },
+        None => ChatScope::Pub {
+            pub_id: message.pub_id,
+        },
+    }
+}
+
+/// Pushes a response to everyone who can see `message`, apart from anyone
+/// who's blocked its author
+async fn broadcast_chat<'a>(
+    conn: &mut DbConnection<'a>,
+    message: &ChatMessage,
+    response: &Response,
+) -> Result<()> {
+    let text = serde_json::to_string(response)?;
+    let blockers = Block::get_blockers(conn, message.author_id).await?;
+    let person_ids = match message.table_id {
+        Some(table_id) => PubTable::get_seated(conn, table_id).await?,
+        None => Pub::get_person_ids(conn, message.pub_id).await?,
+    };
+    for person_id in person_ids {
+        if !blockers.contains(&person_id) {
+            registry::send_to_person(person_id, &text);
+        }
+    }
+    Ok(())
+}
+
+/// Pushes a response to everyone subscribed to the lobby
+fn broadcast_to_lobby(response: &Response) -> Result<()> {
+    let text = serde_json::to_string(response)?;
+    for entry in LOBBY.iter() {
+        let (person_id, session_id) = *entry;
+        registry::send_to_session(person_id, session_id, &text);
+    }
+    Ok(())
+}
+
+/// Tells the lobby who's now in a pub, after someone has arrived or left
+async fn notify_lobby_occupancy<'a>(conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
+    if LOBBY.is_empty()
+        || Pub::load_from_db(conn, pub_id).await?.visibility != PubVisibility::Public
+    {
+        return Ok(());
+    }
+    broadcast_to_lobby(&Response::PubOccupancy {
+        pub_id,
+        persons: Pub::get_person_ids(conn, pub_id).await?,
+    })
+}
+
+/// Pulls the `kind` out of a raw command, so errors can say which command
+/// they're about even when the rest of it didn't parse
+fn command_kind(text: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()?
+        .get("kind")?
+        .as_str()
+        .map(|kind| kind.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_are_lowercased_without_repeats() {
+        assert_eq!(
+            parse_mentions("@Alice and @bob, then @alice again"),
+            vec!["alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn mention_names_stop_at_punctuation() {
+        assert_eq!(
+            parse_mentions("hi @bar_staff-1! (@x)"),
+            vec!["bar_staff-1", "x"]
+        );
+    }
+
+    #[test]
+    fn email_addresses_are_not_mentions() {
+        assert_eq!(parse_mentions("mail me@example.com"), Vec::<String>::new());
+        assert_eq!(parse_mentions("@ @@ nothing"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn single_emoji_are_reactions() {
+        for emoji in [
+            "👍",
+            "❤️",
+            "👍🏽",
+            "👩‍👩‍👧",
+            "🏳️‍🌈",
+            "🇬🇧",
+            "🏴\u{e0067}\u{e0062}\u{e0065}\u{e006e}\u{e0067}\u{e007f}",
+            "#️⃣",
+            "7⃣",
+        ] {
+            assert!(is_single_emoji(emoji), "{:?} was rejected", emoji);
+        }
+    }
+
+    #[test]
+    fn other_text_is_not_a_reaction() {
+        for text in [
+            "",
+            "lol",
+            "a",
+            "👍👍",
+            "👍 ",
+            "🇬",
+            "🇬🇧🇫🇷",
+            "7",
+            "👍\u{200d}",
+            "x👍",
+        ] {
+            assert!(!is_single_emoji(text), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn mentions_are_capped() {
+        let content = (0..MAX_MENTIONS + 5)
+            .map(|i| format!("@person{i}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(parse_mentions(&content).len(), MAX_MENTIONS);
     }
 }
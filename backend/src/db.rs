@@ -1,8 +1,17 @@
+use crate::codes;
 use crate::error::{MyError, Result};
-use crate::types::{DbConnection, Person, Pool, Pub, PubTable, PubWithPeople, TableWithPeople};
+use crate::types::{
+    Account, AuditAction, AuditEvent, Block, ChatMessage, ChatMessageVersion, ChatSearchHit,
+    Conversation, DbConnection, DirectMessage, Notification, NotificationKind, Person, Pool, Pub,
+    PubInvite, PubRole, PubRoleEntry, PubTable, PubVisibility, PubWithPeople, Receipt, TableAccess,
+    TableWithPeople,
+};
+use bb8_postgres::tokio_postgres::Transaction;
 use bb8_postgres::PostgresConnectionManager;
+use chrono::NaiveDateTime;
 use log::warn;
-use postgres::NoTls;
+use postgres::{NoTls, Row};
+use std::collections::HashMap;
 use std::env;
 use std::result::Result as StdResult;
 use uuid::Uuid;
@@ -19,6 +28,9 @@ pub async fn make_pool() -> Pool {
         .expect("Failed to create db pool")
 }
 
+/// How many codes to try for a new pub before giving up
+const MAX_CODE_ATTEMPTS: usize = 10;
+
 fn map_empty<T, E>(res: StdResult<T, E>) -> Result<()>
 where
     E: Into<MyError>,
@@ -26,6 +38,81 @@ where
     res.map(|_| ()).map_err(|e| e.into())
 }
 
+/// Locks a table's row until the transaction ends, and works out its pub and
+/// whether there's a free seat for `person_id` there
+async fn lock_table(
+    transaction: &Transaction<'_>,
+    table_id: Uuid,
+    person_id: Uuid,
+) -> Result<(Uuid, bool)> {
+    let tables = transaction
+        .query(
+            "SELECT pub_id, max_seats FROM pub_table WHERE id = $1 FOR UPDATE",
+            &[&table_id],
+        )
+        .await?;
+    let table = tables
+        .first()
+        .ok_or_else(|| MyError::NotFound(format!("No such table {table_id}")))?;
+    let pub_id = table.get("pub_id");
+    let free = match table.get::<_, Option<i32>>("max_seats") {
+        None => true,
+        Some(max_seats) => {
+            let seated: i64 = transaction
+                .query_one(
+                    "SELECT COUNT(*) AS seated FROM person WHERE table_id = $1 AND id != $2",
+                    &[&table_id, &person_id],
+                )
+                .await?
+                .get("seated");
+            seated < max_seats as i64
+        }
+    };
+    Ok((pub_id, free))
+}
+
+/// SQL for whether anyone sat at table `$1` has blocked `person` and asked for
+/// them to be kept away
+fn kept_off_table(person: &str) -> String {
+    format!("EXISTS (SELECT 1 FROM person_block JOIN person AS blocker ON blocker.id = person_block.blocker_id WHERE blocker.table_id = $1 AND person_block.blocked_id = {person} AND person_block.keep_off_tables)")
+}
+
+/// Once someone's sat down, they don't need to queue, knock or be invited any more
+async fn remove_passes(
+    transaction: &Transaction<'_>,
+    table_id: Uuid,
+    person_id: Uuid,
+) -> Result<()> {
+    for query in [
+        "DELETE FROM table_queue WHERE table_id = $1 AND person_id = $2",
+        "DELETE FROM table_invite WHERE table_id = $1 AND person_id = $2",
+        "DELETE FROM table_knock WHERE table_id = $1 AND person_id = $2",
+    ] {
+        transaction.execute(query, &[&table_id, &person_id]).await?;
+    }
+    Ok(())
+}
+
+impl TableAccess {
+    fn as_db_str(&self) -> &'static str {
+        match self {
+            TableAccess::Open => "Open",
+            TableAccess::Locked => "Locked",
+            TableAccess::Password => "Password",
+            TableAccess::Knock => "Knock",
+        }
+    }
+
+    fn from_db(access: &str) -> TableAccess {
+        match access {
+            "Locked" => TableAccess::Locked,
+            "Password" => TableAccess::Password,
+            "Knock" => TableAccess::Knock,
+            _ => TableAccess::Open,
+        }
+    }
+}
+
 impl Person {
     pub async fn leave_pub<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<()> {
         map_empty(
@@ -51,12 +138,15 @@ impl Person {
         let rows = conn
             .query("SELECT * FROM person WHERE person.id = $1", &[&person_id])
             .await?;
-        let row = rows.first().unwrap();
+        let row = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such person {person_id}")))?;
         Ok(Person {
             id: row.get("id"),
             name: row.get("name"),
             pub_id: row.get("pub_id"),
             table_id: row.get("table_id"),
+            account_id: row.get("account_id"),
             last_updated: row.get("last_updated"),
         })
     }
@@ -71,6 +161,22 @@ impl Person {
         )
     }
 
+    /// Account holders get their saved display name back, even if cleanup
+    /// has removed their person since they were last here
+    pub async fn add_person_for_account<'a>(
+        conn: &DbConnection<'a>,
+        person_id: Uuid,
+        account_id: Uuid,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO person (id, account_id, name) SELECT $1, account.id, account.display_name FROM account WHERE account.id = $2 ON CONFLICT DO NOTHING",
+                &[&person_id, &account_id],
+            )
+            .await,
+        )
+    }
+
     pub async fn set_name<'a>(
         conn: &mut DbConnection<'a>,
         person_id: Uuid,
@@ -113,6 +219,46 @@ impl Person {
         )
     }
 
+    /// Sits someone at a table if there's a free seat, returning the table they
+    /// were at before. The table row stays locked while seats are counted, so
+    /// two people can't both get the last one.
+    pub async fn take_seat<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        table_id: Uuid,
+    ) -> Result<Option<Uuid>> {
+        let transaction = conn.transaction().await?;
+        let (_, free) = lock_table(&transaction, table_id, person_id).await?;
+        if !free {
+            return Err(MyError::TableFull(format!("Table {table_id} is full")));
+        }
+        let kept_off: bool = transaction
+            .query_one(
+                &format!("SELECT {} AS kept_off", kept_off_table("$2")),
+                &[&table_id, &person_id],
+            )
+            .await?
+            .get("kept_off");
+        if kept_off {
+            return Err(MyError::Forbidden(format!(
+                "{person_id} can't sit at table {table_id}"
+            )));
+        }
+        remove_passes(&transaction, table_id, person_id).await?;
+        let previous = transaction
+            .query(
+                "UPDATE person SET last_updated = NOW(), table_id = $2 FROM (SELECT table_id FROM person WHERE id = $1 FOR UPDATE) AS before WHERE person.id = $1 RETURNING before.table_id",
+                &[&person_id, &table_id],
+            )
+            .await?;
+        let previous = previous
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such person {person_id}")))?
+            .get("table_id");
+        transaction.commit().await?;
+        Ok(previous)
+    }
+
     pub async fn update_last<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<()> {
         map_empty(
             conn.execute(
@@ -123,102 +269,1624 @@ impl Person {
         )
     }
 
-    pub async fn cleanup_outdated<'a>(conn: &mut DbConnection<'a>) -> Result<()> {
+    /// Finds who `@name` mentions in a pub's chat mean, going by either
+    /// display name or account username. With a table, only people sat there
+    /// count, as they're the only ones who'll see the message.
+    pub async fn find_mentioned<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        table_id: Option<Uuid>,
+        names: &[String],
+    ) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query(
+                "SELECT DISTINCT person.id FROM person LEFT JOIN account ON account.id = person.account_id WHERE person.pub_id = $1 AND ($2::UUID IS NULL OR person.table_id = $2) AND (LOWER(person.name) = ANY($3) OR LOWER(account.username) = ANY($3))",
+                &[&pub_id, &table_id, &names],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("id"))
+            .collect())
+    }
+
+    /// People who haven't been heard from in a while, and are probably gone
+    pub async fn find_outdated<'a>(conn: &mut DbConnection<'a>) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query(
+                "SELECT id FROM person WHERE person.last_updated < (NOW() - interval '5 minutes')",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("id"))
+            .collect())
+    }
+
+    pub async fn delete<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<()> {
+        map_empty(
+            conn.execute("DELETE FROM person WHERE person.id = $1", &[&person_id])
+                .await,
+        )
+    }
+}
+
+impl Account {
+    fn from_row(row: &Row) -> Account {
+        Account {
+            id: row.get("id"),
+            username: row.get("username"),
+            display_name: row.get("display_name"),
+            preferences: row.get("preferences"),
+        }
+    }
+
+    pub async fn load_from_db<'a>(
+        conn: &mut DbConnection<'a>,
+        account_id: Uuid,
+    ) -> Result<Account> {
+        let rows = conn
+            .query(
+                "SELECT * FROM account WHERE account.id = $1",
+                &[&account_id],
+            )
+            .await?;
+        Ok(Account::from_row(rows.first().ok_or_else(|| {
+            MyError::NotFound(format!("No such account {account_id}"))
+        })?))
+    }
+
+    /// Returns the account and its password hash, if it has one
+    pub async fn load_by_username<'a>(
+        conn: &mut DbConnection<'a>,
+        username: &str,
+    ) -> Result<Option<(Account, Option<String>)>> {
+        let rows = conn
+            .query(
+                "SELECT * FROM account WHERE account.username = $1",
+                &[&username],
+            )
+            .await?;
+        Ok(rows
+            .first()
+            .map(|row| (Account::from_row(row), row.get("password_hash"))))
+    }
+
+    pub async fn load_by_identity<'a>(
+        conn: &mut DbConnection<'a>,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<Account>> {
+        let rows = conn
+            .query(
+                "SELECT account.* FROM account JOIN account_identity ON account_identity.account_id = account.id WHERE account_identity.issuer = $1 AND account_identity.subject = $2",
+                &[&issuer, &subject],
+            )
+            .await?;
+        Ok(rows.first().map(Account::from_row))
+    }
+
+    pub async fn add_identity<'a>(
+        conn: &mut DbConnection<'a>,
+        account_id: Uuid,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO account_identity (issuer, subject, account_id) VALUES ($1, $2, $3)",
+                &[&issuer, &subject, &account_id],
+            )
+            .await,
+        )
+    }
+
+    /// Finds a free username based on `wanted`, by adding numbers to the end
+    /// until one fits
+    pub async fn unique_username<'a>(conn: &mut DbConnection<'a>, wanted: &str) -> Result<String> {
+        let mut candidate = wanted.to_string();
+        let mut suffix = 1;
+        while Account::load_by_username(conn, &candidate).await?.is_some() {
+            suffix += 1;
+            candidate = format!("{wanted}{suffix}");
+        }
+        Ok(candidate)
+    }
+
+    pub async fn add_account<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        password_hash: Option<&str>,
+    ) -> Result<()> {
+        if Account::load_by_username(conn, &self.username)
+            .await?
+            .is_some()
+        {
+            return Err(MyError::Conflict(format!(
+                "Username {} is already taken",
+                self.username
+            )));
+        }
         map_empty(
             conn.execute(
-                "DELETE FROM person WHERE person.last_updated < (NOW() - interval '5 minutes')",
-                &[],
+                // Tokens only have whole seconds, so the first one is issued "before" NOW()
+                "INSERT INTO account (id, username, password_hash, display_name, preferences, sessions_valid_after) VALUES ($1, $2, $3, $4, $5, date_trunc('second', NOW()))",
+                &[
+                    &self.id,
+                    &self.username,
+                    &password_hash,
+                    &self.display_name,
+                    &self.preferences,
+                ],
+            )
+            .await,
+        )
+    }
+
+    pub async fn set_display_name<'a>(
+        conn: &mut DbConnection<'a>,
+        account_id: Uuid,
+        name: &str,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "UPDATE account SET display_name = $2 WHERE account.id = $1",
+                &[&account_id, &name],
+            )
+            .await,
+        )
+    }
+
+    pub async fn set_preferences<'a>(
+        conn: &mut DbConnection<'a>,
+        account_id: Uuid,
+        preferences: &serde_json::Value,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "UPDATE account SET preferences = $2 WHERE account.id = $1",
+                &[&account_id, &preferences],
+            )
+            .await,
+        )
+    }
+
+    /// Whether a token issued at `issued` (a unix timestamp) was issued since
+    /// the account last logged out
+    pub async fn session_still_valid<'a>(
+        conn: &mut DbConnection<'a>,
+        account_id: Uuid,
+        issued: i64,
+    ) -> Result<bool> {
+        let rows = conn
+            .query(
+                "SELECT to_timestamp($2) >= sessions_valid_after AS valid FROM account WHERE account.id = $1",
+                &[&account_id, &(issued as f64)],
+            )
+            .await?;
+        Ok(rows.first().map(|row| row.get("valid")).unwrap_or(false))
+    }
+
+    /// Invalidates every token issued for the account so far
+    pub async fn end_sessions<'a>(conn: &mut DbConnection<'a>, account_id: Uuid) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "UPDATE account SET sessions_valid_after = date_trunc('second', NOW()) WHERE account.id = $1",
+                &[&account_id],
             )
             .await,
         )
     }
 }
 
+fn role_from_db(role: &str) -> PubRole {
+    match role {
+        "Landlord" => PubRole::Landlord,
+        "BarStaff" => PubRole::BarStaff,
+        _ => PubRole::Patron,
+    }
+}
+
+impl PubRole {
+    fn as_db_str(&self) -> &'static str {
+        match self {
+            PubRole::Landlord => "Landlord",
+            PubRole::BarStaff => "BarStaff",
+            PubRole::Patron => "Patron",
+        }
+    }
+}
+
+impl PubVisibility {
+    fn as_db_str(&self) -> &'static str {
+        match self {
+            PubVisibility::Public => "Public",
+            PubVisibility::Unlisted => "Unlisted",
+            PubVisibility::Private => "Private",
+        }
+    }
+
+    fn from_db(visibility: &str) -> PubVisibility {
+        match visibility {
+            "Unlisted" => PubVisibility::Unlisted,
+            "Private" => PubVisibility::Private,
+            _ => PubVisibility::Public,
+        }
+    }
+}
+
+impl PubInvite {
+    fn from_row(row: &Row) -> PubInvite {
+        PubInvite {
+            id: row.get("id"),
+            pub_id: row.get("pub_id"),
+            created_by: row.get("created_by"),
+            expires: row.get("expires"),
+            created: row.get("created"),
+        }
+    }
+}
+
+impl PubWithPeople {
+    fn from_row(row: &Row) -> PubWithPeople {
+        PubWithPeople {
+            id: row.get("id"),
+            name: row.get("name"),
+            owner_id: row.get("owner_id"),
+            visibility: PubVisibility::from_db(row.get("visibility")),
+            code: row.get("code"),
+            slug: row.get("slug"),
+            description: row.get("description"),
+            tags: row.get("tags"),
+            persons: row.get("persons"),
+        }
+    }
+}
+
 impl Pub {
+    /// Only the public ones, as that's all the lobby gets to see
     pub async fn get_pubs<'a>(conn: &mut DbConnection<'a>) -> Result<Vec<PubWithPeople>> {
-        Ok(conn.query("SELECT public_house.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM public_house LEFT JOIN person ON person.pub_id = public_house.id GROUP BY public_house.id", &[]).await?
+        Ok(conn.query("SELECT public_house.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM public_house LEFT JOIN person ON person.pub_id = public_house.id WHERE public_house.visibility = 'Public' GROUP BY public_house.id", &[]).await?
         .iter()
-        .map(|row| PubWithPeople {
+        .map(PubWithPeople::from_row)
+        .collect())
+    }
+
+    pub async fn get_pub<'a>(conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<PubWithPeople> {
+        let rows = conn.query("SELECT public_house.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM public_house LEFT JOIN person ON person.pub_id = public_house.id WHERE public_house.id = $1 GROUP BY public_house.id", &[&pub_id]).await?;
+        Ok(PubWithPeople::from_row(rows.first().ok_or_else(|| {
+            MyError::NotFound(format!("No such pub {pub_id}"))
+        })?))
+    }
+
+    pub async fn load_from_db<'a>(conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<Pub> {
+        let rows = conn
+            .query("SELECT * FROM public_house WHERE id = $1", &[&pub_id])
+            .await?;
+        let row = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such pub {pub_id}")))?;
+        Ok(Pub {
             id: row.get("id"),
             name: row.get("name"),
-            persons: row.get("persons")
-        }).collect())
+            owner_id: row.get("owner_id"),
+            visibility: PubVisibility::from_db(row.get("visibility")),
+            code: row.get("code"),
+            slug: row.get("slug"),
+            description: row.get("description"),
+            tags: row.get("tags"),
+        })
     }
 
-    pub async fn add_pub<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
-        map_empty(
-            conn.execute(
-                "INSERT INTO public_house (id, name) VALUES ($1, $2)",
-                &[&self.id, &self.name],
+    /// Leaves anything that's `None` as it is, and clears the description if
+    /// it's empty
+    pub async fn update<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        name: Option<&str>,
+        description: Option<&str>,
+        tags: Option<&[String]>,
+    ) -> Result<()> {
+        let updated = conn
+            .execute(
+                "UPDATE public_house SET name = COALESCE($2, name), description = CASE WHEN $3::VARCHAR IS NULL THEN description ELSE NULLIF($3, '') END, tags = COALESCE($4, tags) WHERE id = $1",
+                &[&pub_id, &name, &description, &tags],
             )
-            .await,
-        )
+            .await?;
+        if updated == 0 {
+            return Err(MyError::NotFound(format!("No such pub {pub_id}")));
+        }
+        Ok(())
+    }
+
+    /// Codes are checked first, then slugs
+    pub async fn find_by_code<'a>(conn: &mut DbConnection<'a>, code: &str) -> Result<Uuid> {
+        let rows = conn
+            .query(
+                "SELECT id FROM public_house WHERE code = $1 OR slug = $1 ORDER BY code = $1 DESC LIMIT 1",
+                &[&code],
+            )
+            .await?;
+        Ok(rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No pub with the code {code}")))?
+            .get("id"))
+    }
+
+    pub async fn load_by_slug<'a>(conn: &mut DbConnection<'a>, slug: &str) -> Result<Pub> {
+        let rows = conn
+            .query("SELECT id FROM public_house WHERE slug = $1", &[&slug])
+            .await?;
+        let pub_id = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No pub at {slug}")))?
+            .get("id");
+        Pub::load_from_db(conn, pub_id).await
+    }
+
+    pub async fn get_person_ids<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+    ) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query("SELECT id FROM person WHERE person.pub_id = $1", &[&pub_id])
+            .await?
+            .iter()
+            .map(|row| row.get("id"))
+            .collect())
+    }
+
+    /// Picks a new code if ours has already been taken, so `code` might not
+    /// be the one it started with
+    pub async fn add_pub<'a>(&mut self, conn: &mut DbConnection<'a>) -> Result<()> {
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let res = conn
+                .execute(
+                    "INSERT INTO public_house (id, name, owner_id, visibility, code, slug, description, tags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    &[
+                        &self.id,
+                        &self.name,
+                        &self.owner_id,
+                        &self.visibility.as_db_str(),
+                        &self.code,
+                        &self.slug,
+                        &self.description,
+                        &self.tags,
+                    ],
+                )
+                .await;
+            match res
+                .as_ref()
+                .err()
+                .and_then(|e| e.as_db_error())
+                .and_then(|e| e.constraint())
+            {
+                Some("public_house_code_key") => self.code = codes::generate_code(),
+                Some("public_house_slug_key") => {
+                    return Err(MyError::Conflict(format!(
+                        "The slug {} is already taken",
+                        self.slug.as_deref().unwrap_or_default()
+                    )))
+                }
+                _ => return map_empty(res),
+            }
+        }
+        Err(MyError::Other(anyhow::anyhow!(
+            "Couldn't find a free code for pub {}",
+            self.id
+        )))
     }
 
     pub async fn delete_pub<'a>(conn: &mut DbConnection<'a>, pub_id: Uuid) -> Result<()> {
         let patrons = conn
             .query("SELECT id FROM person WHERE person.pub_id = $1", &[&pub_id])
             .await?;
-        if patrons.is_empty() {
-            map_empty(
-                conn.execute("DELETE FROM public_house WHERE id = $1", &[&pub_id])
-                    .await,
+        if !patrons.is_empty() {
+            return Err(MyError::Conflict(format!(
+                "Can't delete pub {pub_id} because there's still {} in it",
+                patrons.len()
+            )));
+        }
+        let tables = conn
+            .query("SELECT id FROM pub_table WHERE pub_id = $1", &[&pub_id])
+            .await?;
+        if !tables.is_empty() {
+            return Err(MyError::Conflict(format!(
+                "Can't delete pub {pub_id} because it still has {} tables",
+                tables.len()
+            )));
+        }
+        let deleted = conn
+            .execute("DELETE FROM public_house WHERE id = $1", &[&pub_id])
+            .await?;
+        if deleted == 0 {
+            return Err(MyError::NotFound(format!("No such pub {pub_id}")));
+        }
+        Ok(())
+    }
+
+    /// Returns false if the pub already has an owner
+    pub async fn claim<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        owner_id: Uuid,
+    ) -> Result<bool> {
+        Ok(conn
+            .execute(
+                "UPDATE public_house SET owner_id = $2 WHERE id = $1 AND owner_id IS NULL",
+                &[&pub_id, &owner_id],
+            )
+            .await?
+            > 0)
+    }
+
+    /// The owner is always a landlord, and everyone else is a patron unless
+    /// they've been given a role
+    pub async fn get_role<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<PubRole> {
+        let rows = conn
+            .query(
+                "SELECT public_house.owner_id, pub_role.role FROM public_house LEFT JOIN pub_role ON pub_role.pub_id = public_house.id AND pub_role.person_id = $2 WHERE public_house.id = $1",
+                &[&pub_id, &person_id],
             )
-        } else {
-            Ok(())
+            .await?;
+        let row = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such pub {pub_id}")))?;
+        // Pubs from before owners were tracked have no owner to be landlord
+        if row.get::<_, Option<Uuid>>("owner_id") == Some(person_id) {
+            return Ok(PubRole::Landlord);
         }
+        Ok(row
+            .get::<_, Option<&str>>("role")
+            .map(role_from_db)
+            .unwrap_or(PubRole::Patron))
     }
-}
 
-impl PubTable {
-    pub async fn get_tables<'a>(
+    /// Everyone with more than the patron role, including the owner
+    pub async fn get_roles<'a>(
         conn: &mut DbConnection<'a>,
         pub_id: Uuid,
-    ) -> Result<Vec<TableWithPeople>> {
-        Ok(conn.query("SELECT pub_table.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM pub_table LEFT JOIN person ON person.table_id = pub_table.id WHERE pub_table.pub_id = $1 GROUP BY pub_table.id", &[&pub_id]).await?
-        .iter()
-        .map(|row| TableWithPeople {
-            id: row.get("id"),
-            name: row.get("name"),
-            pub_id: row.get("pub_id"),
-            persons: row.get("persons")
-        }).collect())
+    ) -> Result<Vec<PubRoleEntry>> {
+        Ok(conn
+            .query(
+                "SELECT owner_id AS person_id, 'Landlord' AS role FROM public_house WHERE id = $1 AND owner_id IS NOT NULL UNION SELECT person_id, role FROM pub_role WHERE pub_id = $1 AND person_id IS DISTINCT FROM (SELECT owner_id FROM public_house WHERE id = $1)",
+                &[&pub_id],
+            )
+            .await?
+            .iter()
+            .map(|row| PubRoleEntry {
+                person_id: row.get("person_id"),
+                role: role_from_db(row.get("role")),
+            })
+            .collect())
     }
 
-    pub async fn add_table<'a>(&self, conn: &mut DbConnection<'a>) -> Result<()> {
+    pub async fn set_role<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+        role: PubRole,
+    ) -> Result<()> {
+        if role == PubRole::Patron {
+            conn.execute(
+                "DELETE FROM pub_role WHERE pub_id = $1 AND person_id = $2",
+                &[&pub_id, &person_id],
+            )
+            .await?;
+            return Ok(());
+        }
         map_empty(
             conn.execute(
-                "INSERT INTO pub_table (id, name, pub_id) VALUES ($1, $2, $3)",
-                &[&self.id, &self.name, &self.pub_id],
+                "INSERT INTO pub_role (pub_id, person_id, role) VALUES ($1, $2, $3) ON CONFLICT (pub_id, person_id) DO UPDATE SET role = EXCLUDED.role",
+                &[&pub_id, &person_id, &role.as_db_str()],
             )
             .await,
         )
     }
 
-    pub async fn delete_table<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Uuid> {
-        let patrons = conn
+    pub async fn is_banned<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(!conn
             .query(
-                "SELECT id FROM person WHERE person.table_id = $1",
-                &[&table_id],
+                "SELECT 1 FROM pub_ban WHERE pub_id = $1 AND person_id = $2 AND (expires IS NULL OR expires > NOW())",
+                &[&pub_id, &person_id],
+            )
+            .await?
+            .is_empty())
+    }
+
+    /// Bans for `duration` seconds, or forever without one. Returns when the
+    /// ban runs out.
+    pub async fn ban<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+        banned_by: Uuid,
+        duration: Option<u64>,
+    ) -> Result<Option<NaiveDateTime>> {
+        let rows = conn
+            .query(
+                "INSERT INTO pub_ban (pub_id, person_id, banned_by, expires) VALUES ($1, $2, $3, NOW() + make_interval(secs => $4)) ON CONFLICT (pub_id, person_id) DO UPDATE SET banned_by = EXCLUDED.banned_by, expires = EXCLUDED.expires, created = NOW() RETURNING expires",
+                &[&pub_id, &person_id, &banned_by, &duration.map(|secs| secs as f64)],
             )
             .await?;
-        if patrons.is_empty() {
-            let pubs = conn
-                .query(
-                    "DELETE FROM pub_table WHERE id = $1 RETURNING pub_id",
-                    &[&table_id],
-                )
-                .await?;
-            Ok(pubs.first().unwrap().get("pub_id"))
-        } else {
-            warn!(
-                "Not deleting {table_id} because there's still {} in it",
-                patrons.len()
-            );
-            let pubs = conn
-                .query("SELECT pub_id FROM pub_table WHERE id = $1", &[&table_id])
-                .await?;
-            Ok(pubs.first().unwrap().get("pub_id"))
+        Ok(rows.first().and_then(|row| row.get("expires")))
+    }
+
+    pub async fn unban<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<()> {
+        let deleted = conn
+            .execute(
+                "DELETE FROM pub_ban WHERE pub_id = $1 AND person_id = $2",
+                &[&pub_id, &person_id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(MyError::NotFound(format!(
+                "{person_id} isn't banned from pub {pub_id}"
+            )));
         }
+        Ok(())
+    }
+
+    pub async fn is_muted<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(!conn
+            .query(
+                "SELECT 1 FROM pub_mute WHERE pub_id = $1 AND person_id = $2",
+                &[&pub_id, &person_id],
+            )
+            .await?
+            .is_empty())
+    }
+
+    pub async fn mute<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+        muted_by: Uuid,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO pub_mute (pub_id, person_id, muted_by) VALUES ($1, $2, $3) ON CONFLICT (pub_id, person_id) DO NOTHING",
+                &[&pub_id, &person_id, &muted_by],
+            )
+            .await,
+        )
+    }
+
+    pub async fn unmute<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<()> {
+        let deleted = conn
+            .execute(
+                "DELETE FROM pub_mute WHERE pub_id = $1 AND person_id = $2",
+                &[&pub_id, &person_id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(MyError::NotFound(format!(
+                "{person_id} isn't muted in pub {pub_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn add_invite<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        created_by: Uuid,
+        ttl: u64,
+    ) -> Result<PubInvite> {
+        let row = conn
+            .query_one(
+                "INSERT INTO pub_invite (id, pub_id, created_by, expires) VALUES ($1, $2, $3, NOW() + make_interval(secs => $4)) RETURNING *",
+                &[&Uuid::new_v4(), &pub_id, &created_by, &(ttl as f64)],
+            )
+            .await?;
+        Ok(PubInvite::from_row(&row))
+    }
+
+    /// Doesn't include the ones that have expired
+    pub async fn get_invites<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+    ) -> Result<Vec<PubInvite>> {
+        Ok(conn
+            .query(
+                "SELECT * FROM pub_invite WHERE pub_id = $1 AND expires > NOW() ORDER BY created",
+                &[&pub_id],
+            )
+            .await?
+            .iter()
+            .map(PubInvite::from_row)
+            .collect())
+    }
+
+    pub async fn is_invite_valid<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        invite_id: Uuid,
+    ) -> Result<bool> {
+        Ok(!conn
+            .query(
+                "SELECT 1 FROM pub_invite WHERE id = $1 AND pub_id = $2 AND expires > NOW()",
+                &[&invite_id, &pub_id],
+            )
+            .await?
+            .is_empty())
+    }
+
+    pub async fn revoke_invite<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        invite_id: Uuid,
+    ) -> Result<()> {
+        let deleted = conn
+            .execute(
+                "DELETE FROM pub_invite WHERE id = $1 AND pub_id = $2",
+                &[&invite_id, &pub_id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(MyError::NotFound(format!(
+                "No such invite {invite_id} in pub {pub_id}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl TableWithPeople {
+    fn from_row(row: &Row) -> TableWithPeople {
+        TableWithPeople {
+            id: row.get("id"),
+            name: row.get("name"),
+            pub_id: row.get("pub_id"),
+            max_seats: row.get("max_seats"),
+            access: TableAccess::from_db(row.get("access")),
+            topic: row.get("topic"),
+            persons: row.get("persons"),
+        }
+    }
+}
+
+impl PubTable {
+    pub async fn get_tables<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+    ) -> Result<Vec<TableWithPeople>> {
+        Ok(conn.query("SELECT pub_table.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM pub_table LEFT JOIN person ON person.table_id = pub_table.id WHERE pub_table.pub_id = $1 GROUP BY pub_table.id", &[&pub_id]).await?
+        .iter()
+        .map(TableWithPeople::from_row)
+        .collect())
+    }
+
+    pub async fn get_table<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+    ) -> Result<TableWithPeople> {
+        let rows = conn.query("SELECT pub_table.*, ARRAY_REMOVE(ARRAY_AGG(person.id), NULL) AS persons FROM pub_table LEFT JOIN person ON person.table_id = pub_table.id WHERE pub_table.id = $1 GROUP BY pub_table.id", &[&table_id]).await?;
+        Ok(TableWithPeople::from_row(rows.first().ok_or_else(
+            || MyError::NotFound(format!("No such table {table_id}")),
+        )?))
+    }
+
+    /// Leaves anything that's `None` as it is, and clears the topic if it's
+    /// empty
+    pub async fn update<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        name: Option<&str>,
+        topic: Option<&str>,
+    ) -> Result<()> {
+        let updated = conn
+            .execute(
+                "UPDATE pub_table SET name = COALESCE($2, name), topic = CASE WHEN $3::VARCHAR IS NULL THEN topic ELSE NULLIF($3, '') END WHERE id = $1",
+                &[&table_id, &name, &topic],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(MyError::NotFound(format!("No such table {table_id}")));
+        }
+        Ok(())
+    }
+
+    pub async fn get_pub_id<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Uuid> {
+        let rows = conn
+            .query("SELECT pub_id FROM pub_table WHERE id = $1", &[&table_id])
+            .await?;
+        Ok(rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such table {table_id}")))?
+            .get("pub_id"))
+    }
+
+    pub async fn add_table<'a>(
+        &self,
+        conn: &mut DbConnection<'a>,
+        password_hash: Option<&str>,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO pub_table (id, name, pub_id, max_seats, access, password_hash, topic) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &self.id,
+                    &self.name,
+                    &self.pub_id,
+                    &self.max_seats,
+                    &self.access.as_db_str(),
+                    &password_hash,
+                    &self.topic,
+                ],
+            )
+            .await,
+        )
+    }
+
+    pub async fn delete_table<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Uuid> {
+        let patrons = conn
+            .query(
+                "SELECT id FROM person WHERE person.table_id = $1",
+                &[&table_id],
+            )
+            .await?;
+        if !patrons.is_empty() {
+            warn!(
+                "Not deleting {table_id} because there's still {} in it",
+                patrons.len()
+            );
+            return Err(MyError::Conflict(format!(
+                "Can't delete table {table_id} because there's still {} in it",
+                patrons.len()
+            )));
+        }
+        let pubs = conn
+            .query(
+                "DELETE FROM pub_table WHERE id = $1 RETURNING pub_id",
+                &[&table_id],
+            )
+            .await?;
+        Ok(pubs
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such table {table_id}")))?
+            .get("pub_id"))
+    }
+
+    /// Returns the table's access, and its password hash if it has one
+    pub async fn get_access<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+    ) -> Result<(TableAccess, Option<String>)> {
+        let rows = conn
+            .query(
+                "SELECT access, password_hash FROM pub_table WHERE id = $1",
+                &[&table_id],
+            )
+            .await?;
+        let row = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such table {table_id}")))?;
+        Ok((
+            TableAccess::from_db(row.get("access")),
+            row.get("password_hash"),
+        ))
+    }
+
+    pub async fn invite<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+        invited_by: Uuid,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO table_invite (table_id, person_id, invited_by) VALUES ($1, $2, $3) ON CONFLICT (table_id, person_id) DO UPDATE SET invited_by = EXCLUDED.invited_by, created = NOW()",
+                &[&table_id, &person_id, &invited_by],
+            )
+            .await,
+        )
+    }
+
+    pub async fn is_invited<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(!conn
+            .query(
+                "SELECT 1 FROM table_invite WHERE table_id = $1 AND person_id = $2",
+                &[&table_id, &person_id],
+            )
+            .await?
+            .is_empty())
+    }
+
+    pub async fn knock<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO table_knock (table_id, person_id) VALUES ($1, $2) ON CONFLICT (table_id, person_id) DO UPDATE SET created = NOW()",
+                &[&table_id, &person_id],
+            )
+            .await,
+        )
+    }
+
+    pub async fn is_knocking<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(!conn
+            .query(
+                "SELECT 1 FROM table_knock WHERE table_id = $1 AND person_id = $2",
+                &[&table_id, &person_id],
+            )
+            .await?
+            .is_empty())
+    }
+
+    /// Returns false if they weren't knocking
+    pub async fn remove_knock<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(conn
+            .execute(
+                "DELETE FROM table_knock WHERE table_id = $1 AND person_id = $2",
+                &[&table_id, &person_id],
+            )
+            .await?
+            > 0)
+    }
+
+    pub async fn get_seated<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query("SELECT id FROM person WHERE table_id = $1", &[&table_id])
+            .await?
+            .iter()
+            .map(|row| row.get("id"))
+            .collect())
+    }
+
+    /// Puts someone at the back of the queue for a table, taking them out of
+    /// any other queue. Returns the table they were queueing for before.
+    pub async fn queue_person<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<Option<Uuid>> {
+        let previous = PubTable::unqueue_person(conn, person_id).await?;
+        conn.execute(
+            "INSERT INTO table_queue (table_id, person_id) VALUES ($1, $2)",
+            &[&table_id, &person_id],
+        )
+        .await?;
+        Ok(previous)
+    }
+
+    /// Returns the table they were queueing for, if any
+    pub async fn unqueue_person<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+    ) -> Result<Option<Uuid>> {
+        Ok(conn
+            .query(
+                "DELETE FROM table_queue WHERE person_id = $1 RETURNING table_id",
+                &[&person_id],
+            )
+            .await?
+            .first()
+            .map(|row| row.get("table_id")))
+    }
+
+    /// Everyone queueing for a table, front of the queue first
+    pub async fn get_queue<'a>(conn: &mut DbConnection<'a>, table_id: Uuid) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query(
+                "SELECT person_id FROM table_queue WHERE table_id = $1 ORDER BY id",
+                &[&table_id],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("person_id"))
+            .collect())
+    }
+
+    /// If there's a free seat, sits the first person in the queue who's still
+    /// in the pub there. Returns who got seated and the table they were at.
+    pub async fn seat_from_queue<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+    ) -> Result<Option<(Uuid, Option<Uuid>)>> {
+        let transaction = conn.transaction().await?;
+        let (pub_id, free) = match lock_table(&transaction, table_id, Uuid::nil()).await {
+            Ok(locked) => locked,
+            // Deleted in the meantime, and the queue with it
+            Err(MyError::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !free {
+            return Ok(None);
+        }
+        // People who've left the pub since queueing lose their place
+        transaction
+            .execute(
+                "DELETE FROM table_queue WHERE table_id = $1 AND NOT EXISTS (SELECT 1 FROM person WHERE person.id = table_queue.person_id AND person.pub_id = $2)",
+                &[&table_id, &pub_id],
+            )
+            .await?;
+        // Anyone kept away by someone sat there stays queued until they leave
+        let next = transaction
+            .query(
+                &format!("DELETE FROM table_queue WHERE id = (SELECT id FROM table_queue WHERE table_id = $1 AND NOT {} ORDER BY id LIMIT 1 FOR UPDATE) RETURNING person_id", kept_off_table("table_queue.person_id")),
+                &[&table_id],
+            )
+            .await?;
+        let person_id: Uuid = match next.first() {
+            Some(row) => row.get("person_id"),
+            None => return Ok(None),
+        };
+        remove_passes(&transaction, table_id, person_id).await?;
+        let previous = transaction
+            .query_one(
+                "UPDATE person SET last_updated = NOW(), table_id = $2 FROM (SELECT table_id FROM person WHERE id = $1 FOR UPDATE) AS before WHERE person.id = $1 RETURNING before.table_id",
+                &[&person_id, &table_id],
+            )
+            .await?
+            .get("table_id");
+        transaction.commit().await?;
+        Ok(Some((person_id, previous)))
+    }
+}
+
+impl AuditAction {
+    fn as_db_str(&self) -> &'static str {
+        match self {
+            AuditAction::Kick => "Kick",
+            AuditAction::Ban => "Ban",
+            AuditAction::Unban => "Unban",
+            AuditAction::Mute => "Mute",
+            AuditAction::Unmute => "Unmute",
+            AuditAction::SetRole => "SetRole",
+            AuditAction::DeleteTable => "DeleteTable",
+            AuditAction::DeletePub => "DeletePub",
+            AuditAction::RevokeInvite => "RevokeInvite",
+            AuditAction::UpdatePub => "UpdatePub",
+            AuditAction::UpdateTable => "UpdateTable",
+            AuditAction::DeleteMessage => "DeleteMessage",
+            AuditAction::ClaimPub => "ClaimPub",
+        }
+    }
+
+    fn from_db(action: &str) -> Result<AuditAction> {
+        Ok(match action {
+            "Kick" => AuditAction::Kick,
+            "Ban" => AuditAction::Ban,
+            "Unban" => AuditAction::Unban,
+            "Mute" => AuditAction::Mute,
+            "Unmute" => AuditAction::Unmute,
+            "SetRole" => AuditAction::SetRole,
+            "DeleteTable" => AuditAction::DeleteTable,
+            "DeletePub" => AuditAction::DeletePub,
+            "RevokeInvite" => AuditAction::RevokeInvite,
+            "UpdatePub" => AuditAction::UpdatePub,
+            "UpdateTable" => AuditAction::UpdateTable,
+            "DeleteMessage" => AuditAction::DeleteMessage,
+            "ClaimPub" => AuditAction::ClaimPub,
+            other => {
+                return Err(MyError::Other(anyhow::anyhow!(format!(
+                    "Unknown audit action {other}"
+                ))))
+            }
+        })
+    }
+}
+
+impl AuditEvent {
+    pub async fn record<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        actor_id: Uuid,
+        action: AuditAction,
+        target_id: Option<Uuid>,
+        reason: Option<&str>,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO audit_event (pub_id, actor_id, action, target_id, reason, details) VALUES ($1, $2, $3, $4, $5, $6)",
+                &[&pub_id, &actor_id, &action.as_db_str(), &target_id, &reason, &details],
+            )
+            .await,
+        )
+    }
+
+    /// Newest first, starting from just before the `before` event if given
+    pub async fn list<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>> {
+        conn.query(
+            "SELECT * FROM audit_event WHERE pub_id = $1 AND ($2::BIGINT IS NULL OR id < $2) ORDER BY id DESC LIMIT $3",
+            &[&pub_id, &before, &limit],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(AuditEvent {
+                id: row.get("id"),
+                pub_id: row.get("pub_id"),
+                actor_id: row.get("actor_id"),
+                action: AuditAction::from_db(row.get("action"))?,
+                target_id: row.get("target_id"),
+                reason: row.get("reason"),
+                details: row.get("details"),
+                created: row.get("created"),
+            })
+        })
+        .collect()
+    }
+}
+
+/// Every chat message column, plus its reactions added up as a JSON list
+const CHAT_MESSAGE_COLUMNS: &str = "chat_message.*, COALESCE((SELECT JSON_AGG(JSON_BUILD_OBJECT('emoji', emoji, 'count', count) ORDER BY first) FROM (SELECT emoji, COUNT(*) AS count, MIN(created) AS first FROM chat_reaction WHERE message_id = chat_message.id GROUP BY emoji) AS counts), '[]') AS reactions";
+
+impl ChatMessage {
+    /// Deleted messages come back as tombstones, without their content
+    fn from_row(row: &Row) -> Result<ChatMessage> {
+        let deleted = row.get::<_, Option<NaiveDateTime>>("deleted").is_some();
+        Ok(ChatMessage {
+            id: row.get("id"),
+            pub_id: row.get("pub_id"),
+            table_id: row.get("table_id"),
+            author_id: row.get("author_id"),
+            content: if deleted {
+                String::new()
+            } else {
+                row.get("content")
+            },
+            created: row.get("created"),
+            edited: row.get("edited"),
+            deleted,
+            reactions: serde_json::from_value(row.get("reactions"))?,
+        })
+    }
+
+    pub async fn post<'a>(
+        conn: &mut DbConnection<'a>,
+        pub_id: Uuid,
+        table_id: Option<Uuid>,
+        author_id: Uuid,
+        content: &str,
+    ) -> Result<ChatMessage> {
+        let row = conn
+            .query_one(
+                "INSERT INTO chat_message (pub_id, table_id, author_id, content) VALUES ($1, $2, $3, $4) RETURNING *, '[]'::JSON AS reactions",
+                &[&pub_id, &table_id, &author_id, &content],
+            )
+            .await?;
+        ChatMessage::from_row(&row)
+    }
+
+    pub async fn load<'a>(conn: &mut DbConnection<'a>, message_id: i64) -> Result<ChatMessage> {
+        let rows = conn
+            .query(
+                &format!("SELECT {CHAT_MESSAGE_COLUMNS} FROM chat_message WHERE id = $1"),
+                &[&message_id],
+            )
+            .await?;
+        ChatMessage::from_row(
+            rows.first()
+                .ok_or_else(|| MyError::NotFound(format!("No such message {message_id}")))?,
+        )
+    }
+
+    /// Newest first, starting from just before the `before` message if given.
+    /// Without a table, that's the chat for the whole pub. Leaves out anyone
+    /// the viewer has blocked.
+    pub async fn history<'a>(
+        conn: &mut DbConnection<'a>,
+        viewer_id: Uuid,
+        pub_id: Uuid,
+        table_id: Option<Uuid>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>> {
+        conn.query(
+            &format!("SELECT {CHAT_MESSAGE_COLUMNS} FROM chat_message WHERE pub_id = $1 AND table_id IS NOT DISTINCT FROM $2 AND ($3::BIGINT IS NULL OR id < $3) AND author_id NOT IN (SELECT blocked_id FROM person_block WHERE blocker_id = $5) ORDER BY id DESC LIMIT $4"),
+            &[&pub_id, &table_id, &before, &limit, &viewer_id],
+        )
+        .await?
+        .iter()
+        .map(ChatMessage::from_row)
+        .collect()
+    }
+
+    /// Best matches first. Table chat is only searched for open tables, and
+    /// for the table the searcher is sat at. Like `history`, leaves out anyone
+    /// the searcher has blocked.
+    pub async fn search<'a>(
+        conn: &mut DbConnection<'a>,
+        viewer_id: Uuid,
+        pub_id: Uuid,
+        my_table_id: Option<Uuid>,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<ChatSearchHit>> {
+        conn.query(
+            &format!("SELECT {CHAT_MESSAGE_COLUMNS}, ts_headline('english', chat_message.content, query, 'StartSel=«, StopSel=», MaxFragments=2, MaxWords=20, MinWords=5') AS snippet FROM chat_message CROSS JOIN websearch_to_tsquery('english', $2) AS query LEFT JOIN pub_table ON pub_table.id = chat_message.table_id WHERE chat_message.pub_id = $1 AND chat_message.search @@ query AND chat_message.deleted IS NULL AND (chat_message.table_id IS NULL OR chat_message.table_id = $3 OR pub_table.access = $4) AND chat_message.author_id NOT IN (SELECT blocked_id FROM person_block WHERE blocker_id = $6) ORDER BY ts_rank(chat_message.search, query) DESC, chat_message.id DESC LIMIT $5"),
+            &[&pub_id, &query, &my_table_id, &TableAccess::Open.as_db_str(), &limit, &viewer_id],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(ChatSearchHit {
+                message: ChatMessage::from_row(row)?,
+                snippet: row.get("snippet"),
+            })
+        })
+        .collect()
+    }
+
+    /// Keeps what the message said before, for moderators to look back at
+    pub async fn edit<'a>(
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+        edited_by: Uuid,
+        content: &str,
+    ) -> Result<()> {
+        let transaction = conn.transaction().await?;
+        let rows = transaction
+            .query(
+                "SELECT content FROM chat_message WHERE id = $1 AND deleted IS NULL FOR UPDATE",
+                &[&message_id],
+            )
+            .await?;
+        let previous: String = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such message {message_id}")))?
+            .get("content");
+        transaction
+            .execute(
+                "INSERT INTO chat_message_edit (message_id, content, edited_by) VALUES ($1, $2, $3)",
+                &[&message_id, &previous, &edited_by],
+            )
+            .await?;
+        transaction
+            .execute(
+                "UPDATE chat_message SET content = $2, edited = NOW() WHERE id = $1",
+                &[&message_id, &content],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Everything the message has said, oldest first, ending with what it
+    /// says now. Deleted messages still have their content here.
+    pub async fn get_versions<'a>(
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+    ) -> Result<Vec<ChatMessageVersion>> {
+        let rows = conn
+            .query(
+                "SELECT content, created, edited FROM chat_message WHERE id = $1",
+                &[&message_id],
+            )
+            .await?;
+        let message = rows
+            .first()
+            .ok_or_else(|| MyError::NotFound(format!("No such message {message_id}")))?;
+        // Each edit keeps the content it replaced, which was written when the
+        // edit before it was made
+        let mut written: NaiveDateTime = message.get("created");
+        let mut versions = vec![];
+        for edit in conn
+            .query(
+                "SELECT content, created FROM chat_message_edit WHERE message_id = $1 ORDER BY id",
+                &[&message_id],
+            )
+            .await?
+        {
+            versions.push(ChatMessageVersion {
+                content: edit.get("content"),
+                written,
+            });
+            written = edit.get("created");
+        }
+        versions.push(ChatMessageVersion {
+            content: message.get("content"),
+            written,
+        });
+        Ok(versions)
+    }
+
+    pub async fn delete<'a>(
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+        deleted_by: Uuid,
+    ) -> Result<()> {
+        let deleted = conn
+            .execute(
+                "UPDATE chat_message SET deleted = NOW(), deleted_by = $2 WHERE id = $1 AND deleted IS NULL",
+                &[&message_id, &deleted_by],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(MyError::NotFound(format!("No such message {message_id}")));
+        }
+        Ok(())
+    }
+
+    /// Reacting again with the same emoji takes the reaction back off
+    pub async fn toggle_reaction<'a>(
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+        person_id: Uuid,
+        emoji: &str,
+    ) -> Result<()> {
+        let removed = conn
+            .execute(
+                "DELETE FROM chat_reaction WHERE message_id = $1 AND person_id = $2 AND emoji = $3",
+                &[&message_id, &person_id, &emoji],
+            )
+            .await?;
+        if removed == 0 {
+            conn.execute(
+                "INSERT INTO chat_reaction (message_id, person_id, emoji) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&message_id, &person_id, &emoji],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+impl DirectMessage {
+    fn from_row(row: &Row) -> DirectMessage {
+        DirectMessage {
+            id: row.get("id"),
+            sender_id: row.get("sender_id"),
+            recipient_id: row.get("recipient_id"),
+            content: row.get("content"),
+            created: row.get("created"),
+            delivered: row.get("delivered"),
+            read: row.get("read"),
+        }
+    }
+
+    pub async fn post<'a>(
+        conn: &mut DbConnection<'a>,
+        sender_id: Uuid,
+        recipient_id: Uuid,
+        content: &str,
+    ) -> Result<DirectMessage> {
+        let row = conn
+            .query_one(
+                "INSERT INTO direct_message (sender_id, recipient_id, content) VALUES ($1, $2, $3) RETURNING *",
+                &[&sender_id, &recipient_id, &content],
+            )
+            .await?;
+        Ok(DirectMessage::from_row(&row))
+    }
+
+    pub async fn has_conversation<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        other_id: Uuid,
+    ) -> Result<bool> {
+        let rows = conn
+            .query(
+                "SELECT 1 FROM direct_message WHERE (sender_id = $1 AND recipient_id = $2) OR (sender_id = $2 AND recipient_id = $1) LIMIT 1",
+                &[&person_id, &other_id],
+            )
+            .await?;
+        Ok(!rows.is_empty())
+    }
+
+    pub async fn set_delivered<'a>(
+        conn: &mut DbConnection<'a>,
+        message_id: i64,
+    ) -> Result<DirectMessage> {
+        let rows = conn
+            .query(
+                "UPDATE direct_message SET delivered = COALESCE(delivered, now()) WHERE id = $1 RETURNING *",
+                &[&message_id],
+            )
+            .await?;
+        Ok(DirectMessage::from_row(rows.first().ok_or_else(|| {
+            MyError::NotFound(format!("No such message {message_id}"))
+        })?))
+    }
+
+    /// Oldest first
+    pub async fn undelivered<'a>(
+        conn: &mut DbConnection<'a>,
+        recipient_id: Uuid,
+    ) -> Result<Vec<DirectMessage>> {
+        Ok(conn
+            .query(
+                "SELECT * FROM direct_message WHERE recipient_id = $1 AND delivered IS NULL ORDER BY id",
+                &[&recipient_id],
+            )
+            .await?
+            .iter()
+            .map(DirectMessage::from_row)
+            .collect())
+    }
+
+    /// Marks messages to the recipient as delivered, or as read (and so
+    /// delivered too), optionally only those from one sender. Returns the
+    /// last message marked for each sender, with when it was marked.
+    pub async fn mark<'a>(
+        conn: &mut DbConnection<'a>,
+        recipient_id: Uuid,
+        sender_id: Option<Uuid>,
+        up_to: i64,
+        receipt: Receipt,
+    ) -> Result<Vec<(Uuid, i64, NaiveDateTime)>> {
+        let query = match receipt {
+            Receipt::Delivered => "UPDATE direct_message SET delivered = now() WHERE recipient_id = $1 AND ($2::UUID IS NULL OR sender_id = $2) AND id <= $3 AND delivered IS NULL RETURNING sender_id, id, delivered AS at",
+            Receipt::Read => "UPDATE direct_message SET read = now(), delivered = COALESCE(delivered, now()) WHERE recipient_id = $1 AND ($2::UUID IS NULL OR sender_id = $2) AND id <= $3 AND read IS NULL RETURNING sender_id, id, read AS at",
+        };
+        let mut last: HashMap<Uuid, (i64, NaiveDateTime)> = HashMap::new();
+        for row in conn
+            .query(query, &[&recipient_id, &sender_id, &up_to])
+            .await?
+        {
+            let (id, at) = (row.get("id"), row.get("at"));
+            let entry = last.entry(row.get("sender_id")).or_insert((id, at));
+            if id > entry.0 {
+                *entry = (id, at);
+            }
+        }
+        Ok(last
+            .into_iter()
+            .map(|(sender_id, (id, at))| (sender_id, id, at))
+            .collect())
+    }
+
+    /// Newest first, starting from just before the `before` message if given
+    pub async fn history<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        other_id: Uuid,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DirectMessage>> {
+        Ok(conn
+            .query(
+                "SELECT * FROM direct_message WHERE ((sender_id = $1 AND recipient_id = $2) OR (sender_id = $2 AND recipient_id = $1)) AND ($3::BIGINT IS NULL OR id < $3) ORDER BY id DESC LIMIT $4",
+                &[&person_id, &other_id, &before, &limit],
+            )
+            .await?
+            .iter()
+            .map(DirectMessage::from_row)
+            .collect())
+    }
+
+    /// Most recent conversation first
+    pub async fn inbox<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+    ) -> Result<Vec<Conversation>> {
+        let unread: HashMap<Uuid, i64> = conn
+            .query(
+                "SELECT sender_id, COUNT(*) AS unread FROM direct_message WHERE recipient_id = $1 AND read IS NULL GROUP BY sender_id",
+                &[&person_id],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get("sender_id"), row.get("unread")))
+            .collect();
+        let mut list: Vec<Conversation> = conn
+            .query(
+                "SELECT DISTINCT ON (other_id) * FROM (SELECT *, CASE WHEN sender_id = $1 THEN recipient_id ELSE sender_id END AS other_id FROM direct_message WHERE sender_id = $1 OR recipient_id = $1) AS mine ORDER BY other_id, id DESC",
+                &[&person_id],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                let other_id = row.get("other_id");
+                Conversation {
+                    person_id: other_id,
+                    last: DirectMessage::from_row(row),
+                    unread: unread.get(&other_id).copied().unwrap_or(0),
+                }
+            })
+            .collect();
+        list.sort_by_key(|conversation| std::cmp::Reverse(conversation.last.id));
+        Ok(list)
+    }
+}
+
+impl Notification {
+    fn from_row(row: &Row) -> Result<Notification> {
+        Ok(Notification {
+            id: row.get("id"),
+            person_id: row.get("person_id"),
+            actor_id: row.get("actor_id"),
+            kind: serde_json::from_value(row.get("details"))?,
+            created: row.get("created"),
+            read: row.get("read"),
+        })
+    }
+
+    pub async fn add<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        actor_id: Option<Uuid>,
+        kind: &NotificationKind,
+    ) -> Result<Notification> {
+        let row = conn
+            .query_one(
+                "INSERT INTO notification (person_id, actor_id, details) VALUES ($1, $2, $3) RETURNING *",
+                &[&person_id, &actor_id, &serde_json::to_value(kind)?],
+            )
+            .await?;
+        Notification::from_row(&row)
+    }
+
+    /// Newest first, starting from just before the `before` notification if
+    /// given
+    pub async fn list<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Notification>> {
+        conn.query(
+            "SELECT * FROM notification WHERE person_id = $1 AND ($2::BIGINT IS NULL OR id < $2) ORDER BY id DESC LIMIT $3",
+            &[&person_id, &before, &limit],
+        )
+        .await?
+        .iter()
+        .map(Notification::from_row)
+        .collect()
+    }
+
+    pub async fn count_unread<'a>(conn: &mut DbConnection<'a>, person_id: Uuid) -> Result<i64> {
+        Ok(conn
+            .query_one(
+                "SELECT COUNT(*) AS unread FROM notification WHERE person_id = $1 AND read IS NULL",
+                &[&person_id],
+            )
+            .await?
+            .get("unread"))
+    }
+
+    pub async fn mark_read<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        up_to: i64,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "UPDATE notification SET read = now() WHERE person_id = $1 AND id <= $2 AND read IS NULL",
+                &[&person_id, &up_to],
+            )
+            .await,
+        )
+    }
+}
+
+impl Block {
+    /// Blocking someone again just updates `keep_off_tables`
+    pub async fn add<'a>(
+        conn: &mut DbConnection<'a>,
+        blocker_id: Uuid,
+        blocked_id: Uuid,
+        keep_off_tables: bool,
+    ) -> Result<()> {
+        map_empty(
+            conn.execute(
+                "INSERT INTO person_block (blocker_id, blocked_id, keep_off_tables) VALUES ($1, $2, $3) ON CONFLICT (blocker_id, blocked_id) DO UPDATE SET keep_off_tables = EXCLUDED.keep_off_tables",
+                &[&blocker_id, &blocked_id, &keep_off_tables],
+            )
+            .await,
+        )
+    }
+
+    pub async fn remove<'a>(
+        conn: &mut DbConnection<'a>,
+        blocker_id: Uuid,
+        blocked_id: Uuid,
+    ) -> Result<()> {
+        let removed = conn
+            .execute(
+                "DELETE FROM person_block WHERE blocker_id = $1 AND blocked_id = $2",
+                &[&blocker_id, &blocked_id],
+            )
+            .await?;
+        if removed == 0 {
+            return Err(MyError::NotFound(format!("{blocked_id} isn't blocked")));
+        }
+        Ok(())
+    }
+
+    /// Most recent first
+    pub async fn list<'a>(conn: &mut DbConnection<'a>, blocker_id: Uuid) -> Result<Vec<Block>> {
+        Ok(conn
+            .query(
+                "SELECT * FROM person_block WHERE blocker_id = $1 ORDER BY created DESC",
+                &[&blocker_id],
+            )
+            .await?
+            .iter()
+            .map(|row| Block {
+                person_id: row.get("blocked_id"),
+                keep_off_tables: row.get("keep_off_tables"),
+                created: row.get("created"),
+            })
+            .collect())
+    }
+
+    /// Everyone who's blocked `blocked_id`
+    pub async fn get_blockers<'a>(
+        conn: &mut DbConnection<'a>,
+        blocked_id: Uuid,
+    ) -> Result<Vec<Uuid>> {
+        Ok(conn
+            .query(
+                "SELECT blocker_id FROM person_block WHERE blocked_id = $1",
+                &[&blocked_id],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("blocker_id"))
+            .collect())
+    }
+
+    /// Whether either of them has blocked the other
+    pub async fn is_between<'a>(
+        conn: &mut DbConnection<'a>,
+        person_id: Uuid,
+        other_id: Uuid,
+    ) -> Result<bool> {
+        let rows = conn
+            .query(
+                "SELECT 1 FROM person_block WHERE (blocker_id = $1 AND blocked_id = $2) OR (blocker_id = $2 AND blocked_id = $1) LIMIT 1",
+                &[&person_id, &other_id],
+            )
+            .await?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Whether someone sat at the table has asked for `person_id` to be kept
+    /// away from them
+    pub async fn is_kept_off_table<'a>(
+        conn: &mut DbConnection<'a>,
+        table_id: Uuid,
+        person_id: Uuid,
+    ) -> Result<bool> {
+        Ok(conn
+            .query_one(
+                &format!("SELECT {} AS kept_off", kept_off_table("$2")),
+                &[&table_id, &person_id],
+            )
+            .await?
+            .get("kept_off"))
     }
 }
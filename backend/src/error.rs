@@ -1,5 +1,8 @@
+use postgres::error::SqlState;
+use serde::{Deserialize, Serialize};
 use std::{env, fmt, io};
 use thiserror::Error;
+use warp::http::StatusCode;
 
 pub type Result<T> = std::result::Result<T, MyError>;
 
@@ -21,12 +24,98 @@ pub enum MyError {
         #[from]
         source: postgres::Error,
     },
+    Pool {
+        #[from]
+        source: bb8::RunError<postgres::Error>,
+    },
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    NotFound(String),
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    InvalidInput(String),
+    TableFull(String),
     #[error(transparent)]
     Other(#[from] anyhow::Error), // source and Display delegate to anyhow::Error
 }
 
+/// Error classification reported back to clients in `Response::Error`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotFound,
+    Conflict,
+    Unauthorized,
+    Forbidden,
+    InvalidInput,
+    TableFull,
+    Internal,
+}
+
+impl MyError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            MyError::NotFound(_) => ErrorCode::NotFound,
+            MyError::Conflict(_) => ErrorCode::Conflict,
+            MyError::Unauthorized(_) => ErrorCode::Unauthorized,
+            MyError::Forbidden(_) => ErrorCode::Forbidden,
+            MyError::TableFull(_) => ErrorCode::TableFull,
+            MyError::InvalidInput(_) | MyError::Uuid { .. } | MyError::Json { .. } => {
+                ErrorCode::InvalidInput
+            }
+            MyError::Postgres { source } => match source.code() {
+                Some(&SqlState::FOREIGN_KEY_VIOLATION) => ErrorCode::NotFound,
+                Some(&SqlState::UNIQUE_VIOLATION) => ErrorCode::Conflict,
+                Some(&SqlState::INVALID_TEXT_REPRESENTATION)
+                | Some(&SqlState::STRING_DATA_RIGHT_TRUNCATION) => ErrorCode::InvalidInput,
+                _ => ErrorCode::Internal,
+            },
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// Message that's safe to show to a client. Internal errors get a generic
+    /// message, as the details belong in the server logs.
+    pub fn public_message(&self) -> String {
+        match self.code() {
+            ErrorCode::Internal => "Internal server error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict | ErrorCode::TableFull => StatusCode::CONFLICT,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::InvalidInput => StatusCode::BAD_REQUEST,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl warp::reject::Reject for MyError {}
+
 impl fmt::Display for MyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            MyError::NotFound(msg)
+            | MyError::Conflict(msg)
+            | MyError::Unauthorized(msg)
+            | MyError::Forbidden(msg)
+            | MyError::InvalidInput(msg)
+            | MyError::TableFull(msg) => write!(f, "{msg}"),
+            MyError::Postgres { source } => match source.as_db_error() {
+                Some(db_error) => write!(f, "{}", db_error.message()),
+                None => write!(f, "{self:?}"),
+            },
+            MyError::Json { source } => write!(f, "{source}"),
+            _ => write!(f, "{self:?}"),
+        }
     }
 }
@@ -0,0 +1,413 @@
+use crate::auth::{self, SessionClaims, SessionRequest, SessionToken};
+use crate::commands::check_name;
+use crate::error::{ErrorCode, MyError, Result};
+use crate::oidc::{self, Identity, OidcConfig, LOGIN_TIMEOUT, OIDC_CONFIG};
+use crate::types::{Account, DbConnection, Person, Pool, Pub};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::{Rejection, Reply};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_USERNAME_LENGTH: usize = 32;
+/// Browsers can't set headers on websockets, so the session token comes in as
+/// a second subprotocol after this one, rather than in the URL where it'd be
+/// logged
+pub const WS_PROTOCOL: &str = "tavern";
+/// Ties an OIDC login to the browser that started it
+pub const OIDC_COOKIE: &str = "tavern_oidc";
+
+lazy_static! {
+    /// Where to send people after logging in at an identity provider, set via
+    /// `FRONTEND_URL`
+    static ref FRONTEND_URL: String = std::env::var("FRONTEND_URL").unwrap_or_else(|_| "/".to_string());
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    code: ErrorCode,
+    message: String,
+}
+
+fn reject(error: MyError) -> Rejection {
+    warp::reject::custom(error)
+}
+
+fn reply_json<T: Serialize>(res: Result<T>) -> std::result::Result<warp::reply::Json, Rejection> {
+    res.map(|value| warp::reply::json(&value)).map_err(reject)
+}
+
+fn bearer_token(authorization: Option<String>) -> Result<String> {
+    authorization
+        .as_deref()
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+        .ok_or_else(|| MyError::Unauthorized("Missing session token".to_string()))
+}
+
+fn valid_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Makes sure there's a person for the session, then signs a token for it
+async fn start_session<'a>(
+    conn: &DbConnection<'a>,
+    person_id: Uuid,
+    account_id: Option<Uuid>,
+) -> Result<SessionToken> {
+    match account_id {
+        Some(account_id) => Person::add_person_for_account(conn, person_id, account_id).await?,
+        None => Person::add_person(conn, person_id).await?,
+    }
+    auth::mint_token(person_id, account_id)
+}
+
+/// Checks the token given when opening the websocket, and works out who it's for
+pub async fn ws_identity(
+    protocols: Option<String>,
+    pool: Pool,
+) -> std::result::Result<SessionClaims, Rejection> {
+    async move {
+        let token = protocols
+            .as_deref()
+            .and_then(|protocols| {
+                let mut protocols = protocols.split(',').map(str::trim);
+                match (protocols.next(), protocols.next()) {
+                    (Some(WS_PROTOCOL), Some(token)) => Some(token.to_string()),
+                    _ => None,
+                }
+            })
+            .ok_or_else(|| MyError::Unauthorized("Missing session token".to_string()))?;
+        let mut conn = pool.get().await?;
+        auth::verify_session(&mut conn, &token).await
+    }
+    .await
+    .map_err(reject)
+}
+
+/// `POST /api/session`: hands out a token for a new guest, or a fresh token for
+/// the same person if given a still-valid one
+pub async fn create_session(body: Bytes, pool: Pool) -> std::result::Result<impl Reply, Rejection> {
+    reply_json(
+        async move {
+            let request: SessionRequest = if body.is_empty() {
+                SessionRequest::default()
+            } else {
+                serde_json::from_slice(&body)?
+            };
+            let mut conn = pool.get().await?;
+            let (person_id, account_id) = match request.token {
+                Some(token) => {
+                    let claims = auth::verify_session(&mut conn, &token).await?;
+                    (claims.person_id, claims.account_id)
+                }
+                None => (Uuid::new_v4(), None),
+            };
+            start_session(&conn, person_id, account_id).await
+        }
+        .await,
+    )
+}
+
+/// `POST /api/register`: creates an account and logs straight into it
+pub async fn register(
+    request: RegisterRequest,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    reply_json(
+        async move {
+            let username = request.username.trim().to_string();
+            if username.is_empty()
+                || username.len() > MAX_USERNAME_LENGTH
+                || !username.chars().all(valid_username_char)
+            {
+                return Err(MyError::InvalidInput(format!(
+                    "Usernames need to be 1-{MAX_USERNAME_LENGTH} letters, numbers, '_' or '-'"
+                )));
+            }
+            if request.password.chars().count() < MIN_PASSWORD_LENGTH {
+                return Err(MyError::InvalidInput(format!(
+                    "Passwords need to be at least {MIN_PASSWORD_LENGTH} characters"
+                )));
+            }
+            let display_name = match request.display_name {
+                Some(display_name) => check_name(&display_name)?,
+                None => username.clone(),
+            };
+            let account = Account {
+                id: Uuid::new_v4(),
+                display_name: Some(display_name),
+                username,
+                preferences: serde_json::json!({}),
+            };
+            let password_hash = auth::hash_password(request.password).await?;
+            let mut conn = pool.get().await?;
+            account.add_account(&mut conn, Some(&password_hash)).await?;
+            info!("Registered {} as {}", account.username, account.id);
+            // Account holders use their account id as their person id, so they
+            // show up as the same person on every device
+            start_session(&conn, account.id, Some(account.id)).await
+        }
+        .await,
+    )
+}
+
+/// `POST /api/login`
+pub async fn login(
+    request: LoginRequest,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    reply_json(
+        async move {
+            let mut conn = pool.get().await?;
+            let bad_login = || MyError::Unauthorized("Wrong username or password".to_string());
+            let (account, password_hash) =
+                Account::load_by_username(&mut conn, request.username.trim())
+                    .await?
+                    .ok_or_else(bad_login)?;
+            // Accounts from an identity provider can't log in with a password
+            let password_hash = password_hash.ok_or_else(bad_login)?;
+            if !auth::verify_password(request.password, password_hash).await? {
+                return Err(bad_login());
+            }
+            start_session(&conn, account.id, Some(account.id)).await
+        }
+        .await,
+    )
+}
+
+/// `POST /api/logout`: ends every session for the account in the
+/// `Authorization: Bearer` token. Guest tokens just expire.
+pub async fn logout(
+    authorization: Option<String>,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    async move {
+        let token = bearer_token(authorization)?;
+        let mut conn = pool.get().await?;
+        let claims = auth::verify_session(&mut conn, &token).await?;
+        if let Some(account_id) = claims.account_id {
+            Account::end_sessions(&mut conn, account_id).await?;
+            info!("Logged out {}", account_id);
+        }
+        Ok(StatusCode::NO_CONTENT)
+    }
+    .await
+    .map_err(reject)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OidcSessionRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OidcLinkResponse {
+    url: String,
+}
+
+fn oidc_config() -> Result<&'static OidcConfig> {
+    OIDC_CONFIG
+        .as_ref()
+        .ok_or_else(|| MyError::NotFound("OIDC login isn't configured".to_string()))
+}
+
+fn redirect_to(location: &str) -> Result<warp::reply::WithHeader<StatusCode>> {
+    Ok(warp::reply::with_header(
+        StatusCode::FOUND,
+        warp::http::header::LOCATION,
+        location,
+    ))
+}
+
+fn oidc_cookie(value: &str, max_age: u64) -> String {
+    format!(
+        "{OIDC_COOKIE}={value}; Path=/api/oidc; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax"
+    )
+}
+
+/// `GET /api/oidc/login`: sends the browser off to the identity provider
+pub async fn oidc_login() -> std::result::Result<impl Reply, Rejection> {
+    async move {
+        let (url, browser) = oidc_config()?.begin_login(None).await?;
+        Ok(warp::reply::with_header(
+            redirect_to(&url)?,
+            warp::http::header::SET_COOKIE,
+            oidc_cookie(&browser, LOGIN_TIMEOUT.as_secs()),
+        ))
+    }
+    .await
+    .map_err(reject)
+}
+
+/// `POST /api/oidc/link`: starts a login that links the identity to the
+/// account in the `Authorization: Bearer` token. Replies with where to send
+/// the browser.
+pub async fn oidc_link(
+    authorization: Option<String>,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    async move {
+        let config = oidc_config()?;
+        let token = bearer_token(authorization)?;
+        let mut conn = pool.get().await?;
+        let claims = auth::verify_session(&mut conn, &token).await?;
+        let account_id = claims
+            .account_id
+            .ok_or_else(|| MyError::Forbidden("Only accounts can be linked".to_string()))?;
+        let (url, browser) = config.begin_login(Some(account_id)).await?;
+        Ok(warp::reply::with_header(
+            warp::reply::json(&OidcLinkResponse { url }),
+            warp::http::header::SET_COOKIE,
+            oidc_cookie(&browser, LOGIN_TIMEOUT.as_secs()),
+        ))
+    }
+    .await
+    .map_err(reject)
+}
+
+/// Finds the account for an identity, linking or creating one as needed
+async fn account_for_identity<'a>(
+    conn: &mut DbConnection<'a>,
+    identity: &Identity,
+) -> Result<Uuid> {
+    if let Some(account) =
+        Account::load_by_identity(conn, &identity.issuer, &identity.subject).await?
+    {
+        return Ok(account.id);
+    }
+    let account_id = match identity.link_account {
+        Some(account_id) => {
+            info!("Linking {} to {}", identity.subject, account_id);
+            account_id
+        }
+        None => {
+            let wanted: String = identity
+                .preferred_username
+                .as_deref()
+                .and_then(|username| username.split('@').next())
+                .unwrap_or("user")
+                .chars()
+                .filter(|c| valid_username_char(*c))
+                .take(MAX_USERNAME_LENGTH - 4)
+                .collect();
+            let wanted = if wanted.is_empty() {
+                "user".to_string()
+            } else {
+                wanted
+            };
+            let account = Account {
+                id: Uuid::new_v4(),
+                username: Account::unique_username(conn, &wanted).await?,
+                display_name: identity.name.clone().or_else(|| Some(wanted.clone())),
+                preferences: serde_json::json!({}),
+            };
+            account.add_account(conn, None).await?;
+            info!("Registered {} as {} via OIDC", account.username, account.id);
+            account.id
+        }
+    };
+    Account::add_identity(conn, account_id, &identity.issuer, &identity.subject).await?;
+    Ok(account_id)
+}
+
+/// `GET /api/oidc/callback`: where the identity provider sends people back to.
+/// Logs them in and hands the frontend a one-time code to swap for a session.
+pub async fn oidc_callback(
+    query: OidcCallbackQuery,
+    browser: Option<String>,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    async move {
+        let config = oidc_config()?;
+        if let Some(error) = query.error {
+            return Err(MyError::Unauthorized(format!("Login failed: {error}")));
+        }
+        let (code, state) = query
+            .code
+            .zip(query.state)
+            .ok_or_else(|| MyError::InvalidInput("Missing code or state".to_string()))?;
+        let identity = config.finish_login(code, &state, browser).await?;
+        let mut conn = pool.get().await?;
+        let account_id = account_for_identity(&mut conn, &identity).await?;
+        Ok(warp::reply::with_header(
+            redirect_to(&format!(
+                "{}?login={}",
+                *FRONTEND_URL,
+                oidc::hand_over(account_id)
+            ))?,
+            warp::http::header::SET_COOKIE,
+            oidc_cookie("", 0),
+        ))
+    }
+    .await
+    .map_err(reject)
+}
+
+/// `POST /api/oidc/session`: swaps the code from `oidc_callback` for a session
+pub async fn oidc_session(
+    request: OidcSessionRequest,
+    pool: Pool,
+) -> std::result::Result<impl Reply, Rejection> {
+    reply_json(
+        async move {
+            let account_id = oidc::take_handover(&request.code)?;
+            let conn = pool.get().await?;
+            start_session(&conn, account_id, Some(account_id)).await
+        }
+        .await,
+    )
+}
+
+/// `GET /p/{slug}`: short links to a pub, which hand its code over to the
+/// frontend to join with
+pub async fn pub_link(slug: String, pool: Pool) -> std::result::Result<impl Reply, Rejection> {
+    async move {
+        let mut conn = pool.get().await?;
+        let found = Pub::load_by_slug(&mut conn, &slug.to_lowercase()).await?;
+        redirect_to(&format!("{}#/Join/{}", *FRONTEND_URL, found.code))
+    }
+    .await
+    .map_err(reject)
+}
+
+/// Turns our errors into JSON responses with a matching status code
+pub async fn handle_rejection(rejection: Rejection) -> std::result::Result<impl Reply, Rejection> {
+    match rejection.find::<MyError>() {
+        Some(error) => {
+            let code = error.code();
+            warn!("HTTP error ({:?}): {}", code, error);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&ErrorBody {
+                    code,
+                    message: error.public_message(),
+                }),
+                code.status(),
+            ))
+        }
+        None => Err(rejection),
+    }
+}
@@ -1,11 +1,18 @@
+mod auth;
+mod codes;
 mod commands;
 mod db;
 mod error;
+mod http;
 mod migrations;
+mod oidc;
+mod registry;
+mod signaling;
 mod types;
 
+use crate::auth::SessionClaims;
 use crate::types::{Client, Person};
-use log::info;
+use log::{info, warn};
 use std::convert::Infallible;
 use std::io;
 use std::net::SocketAddr;
@@ -19,14 +26,27 @@ use uuid::Uuid;
 use warp::ws::WebSocket;
 use warp::Filter;
 
-async fn websocket(id_str: String, ws: WebSocket, pool: types::Pool) {
+const JSON_BODY_LIMIT: u64 = 16 * 1024;
+
+async fn websocket(claims: SessionClaims, ws: WebSocket, pool: types::Pool) {
     info!("starting websocket");
-    let id = Uuid::parse_str(&id_str).unwrap();
-    let conn = pool.get().await.unwrap();
-    Person::add_person(&conn, id).await.unwrap();
-    info!("Connected for {}", id_str);
+    let id = claims.person_id;
+    let res = match pool.get().await {
+        Ok(conn) => match claims.account_id {
+            Some(account_id) => Person::add_person_for_account(&conn, id, account_id).await,
+            None => Person::add_person(&conn, id).await,
+        },
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = res {
+        warn!("Failed to set up {}, dropping connection: {}", id, e);
+        return;
+    }
+    info!("Connected for {}", id);
     Client {
         id,
+        session_id: Uuid::new_v4(),
+        account_id: claims.account_id,
         pool: pool.clone(),
     }
     .run_user(ws)
@@ -56,9 +76,14 @@ async fn main() -> io::Result<()> {
     task::spawn(async move {
         loop {
             info!("Start cleanup");
-            let mut conn = thread_pool.get().await.unwrap();
-            Person::cleanup_outdated(&mut conn).await.unwrap();
-            info!("Cleanup done");
+            let res = match thread_pool.get().await {
+                Ok(mut conn) => commands::cleanup_outdated(&mut conn).await,
+                Err(e) => Err(e.into()),
+            };
+            match res {
+                Ok(()) => info!("Cleanup done"),
+                Err(e) => warn!("Cleanup failed: {}", e),
+            }
             if timeout(Duration::from_secs(60), thread_notifier.notified())
                 .await
                 .is_ok()
@@ -68,14 +93,82 @@ async fn main() -> io::Result<()> {
         }
     });
 
-    let ws = warp::path!("ws" / String)
+    let ws = warp::path!("ws")
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(with_db(pool.clone()))
+        .and_then(http::ws_identity)
         .and(warp::ws())
         .and(with_db(pool.clone()))
-        .map(|id: String, ws: warp::ws::Ws, pool: types::Pool| {
-            info!("WS");
-            ws.on_upgrade(move |socket| websocket(id, socket, pool))
-        });
-    let routes = ws;
+        .map(
+            |claims: SessionClaims, ws: warp::ws::Ws, pool: types::Pool| {
+                info!("WS");
+                // Browsers drop the connection unless we pick one of their
+                // subprotocols
+                warp::reply::with_header(
+                    ws.on_upgrade(move |socket| websocket(claims, socket, pool)),
+                    "sec-websocket-protocol",
+                    http::WS_PROTOCOL,
+                )
+            },
+        );
+    let session = warp::path!("api" / "session")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(JSON_BODY_LIMIT))
+        .and(warp::body::bytes())
+        .and(with_db(pool.clone()))
+        .and_then(http::create_session);
+    let register = warp::path!("api" / "register")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(JSON_BODY_LIMIT))
+        .and(warp::body::json())
+        .and(with_db(pool.clone()))
+        .and_then(http::register);
+    let login = warp::path!("api" / "login")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(JSON_BODY_LIMIT))
+        .and(warp::body::json())
+        .and(with_db(pool.clone()))
+        .and_then(http::login);
+    let logout = warp::path!("api" / "logout")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_db(pool.clone()))
+        .and_then(http::logout);
+    let oidc_login = warp::path!("api" / "oidc" / "login")
+        .and(warp::get())
+        .and_then(http::oidc_login);
+    let oidc_link = warp::path!("api" / "oidc" / "link")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_db(pool.clone()))
+        .and_then(http::oidc_link);
+    let oidc_callback = warp::path!("api" / "oidc" / "callback")
+        .and(warp::get())
+        .and(warp::query::<http::OidcCallbackQuery>())
+        .and(warp::cookie::optional::<String>(http::OIDC_COOKIE))
+        .and(with_db(pool.clone()))
+        .and_then(http::oidc_callback);
+    let oidc_session = warp::path!("api" / "oidc" / "session")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(JSON_BODY_LIMIT))
+        .and(warp::body::json())
+        .and(with_db(pool.clone()))
+        .and_then(http::oidc_session);
+    let pub_link = warp::path!("p" / String)
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(http::pub_link);
+    let routes = ws
+        .or(session)
+        .or(register)
+        .or(login)
+        .or(logout)
+        .or(oidc_login)
+        .or(oidc_link)
+        .or(oidc_callback)
+        .or(oidc_session)
+        .or(pub_link)
+        .recover(http::handle_rejection);
 
     warp::serve(routes)
         .run("0.0.0.0:5000".parse::<SocketAddr>().unwrap())
@@ -0,0 +1,15 @@
+CREATE TABLE "account" (
+    id UUID PRIMARY KEY,
+    username VARCHAR NOT NULL UNIQUE,
+    password_hash VARCHAR NOT NULL,
+    display_name VARCHAR NULL,
+    preferences JSONB NOT NULL DEFAULT '{}',
+    sessions_valid_after TIMESTAMP NOT NULL DEFAULT now(),
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
+
+ALTER TABLE "person"
+ADD COLUMN account_id UUID NULL,
+ADD CONSTRAINT fk_person_account
+FOREIGN KEY (account_id)
+REFERENCES account (id);
@@ -0,0 +1,12 @@
+-- Accounts from an identity provider don't have a password
+ALTER TABLE "account" ALTER COLUMN password_hash DROP NOT NULL;
+
+CREATE TABLE "account_identity" (
+    issuer VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    account_id UUID NOT NULL,
+    PRIMARY KEY (issuer, subject),
+    CONSTRAINT fk_identity_account
+    FOREIGN KEY (account_id)
+    REFERENCES account (id)
+);
@@ -0,0 +1,14 @@
+-- Person who created the pub. Pubs from before this don't have one.
+ALTER TABLE "public_house" ADD COLUMN owner_id UUID NULL;
+
+-- Anyone without a role here is a patron
+CREATE TABLE "pub_role" (
+    pub_id UUID NOT NULL,
+    person_id UUID NOT NULL,
+    role VARCHAR NOT NULL CHECK (role IN ('Landlord', 'BarStaff')),
+    PRIMARY KEY (pub_id, person_id),
+    CONSTRAINT fk_role_pub
+    FOREIGN KEY (pub_id)
+    REFERENCES public_house (id)
+    ON DELETE CASCADE
+);
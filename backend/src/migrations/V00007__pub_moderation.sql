@@ -0,0 +1,25 @@
+CREATE TABLE "pub_ban" (
+    pub_id UUID NOT NULL,
+    person_id UUID NOT NULL,
+    banned_by UUID NOT NULL,
+    -- NULL for bans that last forever
+    expires TIMESTAMP NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (pub_id, person_id),
+    CONSTRAINT fk_ban_pub
+    FOREIGN KEY (pub_id)
+    REFERENCES public_house (id)
+    ON DELETE CASCADE
+);
+
+CREATE TABLE "pub_mute" (
+    pub_id UUID NOT NULL,
+    person_id UUID NOT NULL,
+    muted_by UUID NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (pub_id, person_id),
+    CONSTRAINT fk_mute_pub
+    FOREIGN KEY (pub_id)
+    REFERENCES public_house (id)
+    ON DELETE CASCADE
+);
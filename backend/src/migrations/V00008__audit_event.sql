@@ -0,0 +1,24 @@
+-- No foreign key to the pub, so the history outlives it
+CREATE TABLE "audit_event" (
+    id BIGSERIAL PRIMARY KEY,
+    pub_id UUID NOT NULL,
+    actor_id UUID NOT NULL,
+    action VARCHAR NOT NULL,
+    -- Person, table or pub the action was done to
+    target_id UUID NULL,
+    reason VARCHAR NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_event_pub ON audit_event (pub_id, id);
+
+CREATE FUNCTION audit_event_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_event is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_event_append_only
+BEFORE UPDATE OR DELETE ON audit_event
+FOR EACH ROW EXECUTE FUNCTION audit_event_append_only();
@@ -0,0 +1,2 @@
+-- NULL for tables anyone can squeeze onto
+ALTER TABLE "pub_table" ADD COLUMN max_seats INTEGER NULL CHECK (max_seats > 0);
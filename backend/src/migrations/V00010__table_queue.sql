@@ -0,0 +1,14 @@
+-- People waiting for a seat, in order of id. Someone can only queue for one
+-- table at a time.
+CREATE TABLE "table_queue" (
+    id BIGSERIAL PRIMARY KEY,
+    table_id UUID NOT NULL,
+    person_id UUID NOT NULL UNIQUE,
+    queued TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT fk_queue_table
+    FOREIGN KEY (table_id)
+    REFERENCES pub_table (id)
+    ON DELETE CASCADE
+);
+
+CREATE INDEX table_queue_table ON table_queue (table_id, id);
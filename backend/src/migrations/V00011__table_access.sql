@@ -0,0 +1,28 @@
+ALTER TABLE "pub_table"
+ADD COLUMN access VARCHAR NOT NULL DEFAULT 'Open' CHECK (access IN ('Open', 'Locked', 'Password', 'Knock')),
+ADD COLUMN password_hash VARCHAR NULL;
+
+-- Lets someone into a table they couldn't otherwise join, once
+CREATE TABLE "table_invite" (
+    table_id UUID NOT NULL,
+    person_id UUID NOT NULL,
+    invited_by UUID NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (table_id, person_id),
+    CONSTRAINT fk_invite_table
+    FOREIGN KEY (table_id)
+    REFERENCES pub_table (id)
+    ON DELETE CASCADE
+);
+
+-- People waiting to be let into a knock-to-enter table
+CREATE TABLE "table_knock" (
+    table_id UUID NOT NULL,
+    person_id UUID NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (table_id, person_id),
+    CONSTRAINT fk_knock_table
+    FOREIGN KEY (table_id)
+    REFERENCES pub_table (id)
+    ON DELETE CASCADE
+);
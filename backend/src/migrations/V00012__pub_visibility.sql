@@ -0,0 +1,16 @@
+ALTER TABLE "public_house"
+ADD COLUMN visibility VARCHAR NOT NULL DEFAULT 'Public' CHECK (visibility IN ('Public', 'Unlisted', 'Private'));
+
+-- Invite links hand out a signed token naming one of these, so deleting the
+-- row revokes the link
+CREATE TABLE "pub_invite" (
+    id UUID PRIMARY KEY,
+    pub_id UUID NOT NULL,
+    created_by UUID NOT NULL,
+    expires TIMESTAMP NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT fk_invite_pub
+    FOREIGN KEY (pub_id)
+    REFERENCES public_house (id)
+    ON DELETE CASCADE
+);
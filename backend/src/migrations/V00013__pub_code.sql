@@ -0,0 +1,32 @@
+ALTER TABLE "public_house"
+ADD COLUMN code VARCHAR NULL,
+ADD COLUMN slug VARCHAR NULL,
+ADD CONSTRAINT public_house_code_key UNIQUE (code),
+ADD CONSTRAINT public_house_slug_key UNIQUE (slug);
+
+-- Give the pubs we've already got a code, in the same word-word-number form
+-- as new ones, trying again whenever one's already been used
+DO $$
+DECLARE
+    adjectives VARCHAR[] := ARRAY['amber', 'brass', 'cosy', 'crooked', 'dusty', 'golden', 'green', 'hazy', 'hoppy', 'jolly', 'lazy', 'little', 'lucky', 'mellow', 'merry', 'misty', 'noble', 'old', 'plucky', 'quiet', 'red', 'royal', 'rusty', 'silver', 'sleepy', 'snug', 'stout', 'sunny', 'tipsy', 'wandering', 'white', 'wild'];
+    nouns VARCHAR[] := ARRAY['anchor', 'badger', 'barrel', 'bell', 'boar', 'cask', 'crown', 'dragon', 'drum', 'falcon', 'fiddle', 'fox', 'goose', 'griffin', 'hare', 'harp', 'hart', 'horse', 'kettle', 'lamb', 'lantern', 'lion', 'mermaid', 'otter', 'owl', 'plough', 'raven', 'rose', 'ship', 'swan', 'tankard', 'wheatsheaf'];
+    pub_id UUID;
+BEGIN
+    FOR pub_id IN SELECT id FROM public_house LOOP
+        LOOP
+            BEGIN
+                UPDATE public_house SET code =
+                    adjectives[1 + floor(random() * array_length(adjectives, 1))::INT] || '-' ||
+                    nouns[1 + floor(random() * array_length(nouns, 1))::INT] || '-' ||
+                    (10 + floor(random() * 90)::INT)
+                WHERE id = pub_id;
+                EXIT;
+            EXCEPTION WHEN unique_violation THEN
+                -- Roll the dice again
+            END;
+        END LOOP;
+    END LOOP;
+END;
+$$;
+
+ALTER TABLE "public_house" ALTER COLUMN code SET NOT NULL;
@@ -0,0 +1,6 @@
+ALTER TABLE "public_house"
+ADD COLUMN description VARCHAR NULL,
+ADD COLUMN tags VARCHAR[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE "pub_table"
+ADD COLUMN topic VARCHAR NULL;
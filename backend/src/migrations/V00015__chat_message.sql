@@ -0,0 +1,19 @@
+-- Chat for a whole pub has no table
+CREATE TABLE "chat_message" (
+    id BIGSERIAL PRIMARY KEY,
+    pub_id UUID NOT NULL,
+    table_id UUID NULL,
+    author_id UUID NOT NULL,
+    content VARCHAR NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT fk_chat_pub
+    FOREIGN KEY (pub_id)
+    REFERENCES public_house (id)
+    ON DELETE CASCADE,
+    CONSTRAINT fk_chat_table
+    FOREIGN KEY (table_id)
+    REFERENCES pub_table (id)
+    ON DELETE CASCADE
+);
+
+CREATE INDEX chat_message_scope ON chat_message (pub_id, table_id, id);
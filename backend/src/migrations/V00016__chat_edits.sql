@@ -0,0 +1,32 @@
+-- Deleted messages keep their content, but it's only shown to moderators
+ALTER TABLE "chat_message"
+ADD COLUMN edited TIMESTAMP NULL,
+ADD COLUMN deleted TIMESTAMP NULL,
+ADD COLUMN deleted_by UUID NULL;
+
+-- What messages said before each edit
+CREATE TABLE "chat_message_edit" (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL,
+    content VARCHAR NOT NULL,
+    edited_by UUID NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT fk_edit_message
+    FOREIGN KEY (message_id)
+    REFERENCES chat_message (id)
+    ON DELETE CASCADE
+);
+
+CREATE INDEX chat_message_edit_message ON chat_message_edit (message_id, id);
+
+CREATE TABLE "chat_reaction" (
+    message_id BIGINT NOT NULL,
+    person_id UUID NOT NULL,
+    emoji VARCHAR NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, person_id, emoji),
+    CONSTRAINT fk_reaction_message
+    FOREIGN KEY (message_id)
+    REFERENCES chat_message (id)
+    ON DELETE CASCADE
+);
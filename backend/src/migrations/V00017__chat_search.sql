@@ -0,0 +1,5 @@
+-- For SearchMessages. Edits update it along with the content.
+ALTER TABLE "chat_message"
+ADD COLUMN search TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', content)) STORED;
+
+CREATE INDEX chat_message_search ON chat_message USING GIN (search);
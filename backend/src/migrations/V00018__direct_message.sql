@@ -0,0 +1,15 @@
+-- No foreign keys to person, as people are cleaned up while they're away and
+-- their messages need to be waiting for them when they come back
+CREATE TABLE "direct_message" (
+    id BIGSERIAL PRIMARY KEY,
+    sender_id UUID NOT NULL,
+    recipient_id UUID NOT NULL,
+    content VARCHAR NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    delivered TIMESTAMP NULL,
+    read TIMESTAMP NULL
+);
+
+CREATE INDEX direct_message_sender ON direct_message (sender_id, recipient_id, id);
+CREATE INDEX direct_message_recipient ON direct_message (recipient_id, sender_id, id);
+CREATE INDEX direct_message_undelivered ON direct_message (recipient_id, id) WHERE delivered IS NULL;
@@ -0,0 +1,14 @@
+-- No foreign keys, so notifications are still there for people who come back
+-- after being cleaned up, or about pubs and tables that have since gone
+CREATE TABLE "notification" (
+    id BIGSERIAL PRIMARY KEY,
+    person_id UUID NOT NULL,
+    actor_id UUID NULL,
+    -- What happened, as a tagged `NotificationKind`
+    details JSONB NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    read TIMESTAMP NULL
+);
+
+CREATE INDEX notification_person ON notification (person_id, id);
+CREATE INDEX notification_unread ON notification (person_id, id) WHERE read IS NULL;
@@ -0,0 +1,13 @@
+-- No foreign keys to person, so blocks outlast people being cleaned up.
+-- Account holders' person ids are their account ids, so theirs follow them
+-- onto every device.
+CREATE TABLE "person_block" (
+    blocker_id UUID NOT NULL,
+    blocked_id UUID NOT NULL,
+    -- Stops them sitting down at any table the blocker is at
+    keep_off_tables BOOLEAN NOT NULL DEFAULT false,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (blocker_id, blocked_id)
+);
+
+CREATE INDEX person_block_blocked ON person_block (blocked_id);
@@ -0,0 +1,184 @@
+use crate::error::{MyError, Result};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use log::info;
+use openidconnect::core::{CoreClient, CoreProviderMetadata, CoreResponseType};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// How long someone has to finish logging in at the identity provider
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long the frontend has to swap a login code for a session
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Set up from `OIDC_ISSUER_URL`, `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET` and
+    /// `OIDC_REDIRECT_URL`. OIDC login is turned off unless the first two are set.
+    pub static ref OIDC_CONFIG: Option<OidcConfig> = OidcConfig::from_env();
+    /// Logins that have gone off to the identity provider, keyed by CSRF state
+    static ref PENDING: DashMap<String, PendingLogin> = DashMap::new();
+    /// Finished logins waiting for the frontend to pick up a session, keyed by
+    /// one-time code
+    static ref HANDOVERS: DashMap<String, (Uuid, Instant)> = DashMap::new();
+}
+
+static PROVIDER: OnceCell<CoreProviderMetadata> = OnceCell::const_new();
+
+pub struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+}
+
+struct PendingLogin {
+    /// Also kept in a cookie, so only the browser that started the login can
+    /// finish it
+    browser: String,
+    pkce_verifier: PkceCodeVerifier,
+    nonce: Nonce,
+    link_account: Option<Uuid>,
+    started: Instant,
+}
+
+/// Who the identity provider says someone is
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+    /// The account that was logged in when this login started, if any
+    pub link_account: Option<Uuid>,
+}
+
+impl OidcConfig {
+    fn from_env() -> Option<OidcConfig> {
+        Some(OidcConfig {
+            issuer: env::var("OIDC_ISSUER_URL").ok()?,
+            client_id: env::var("OIDC_CLIENT_ID").ok()?,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_url: env::var("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|_| "https://localhost:8000/api/oidc/callback".to_string()),
+        })
+    }
+
+    async fn client(&self) -> Result<CoreClient> {
+        let provider = PROVIDER
+            .get_or_try_init(|| async {
+                info!("Discovering OIDC provider at {}", self.issuer);
+                let issuer =
+                    IssuerUrl::new(self.issuer.clone()).map_err(|e| MyError::Other(e.into()))?;
+                CoreProviderMetadata::discover_async(issuer, async_http_client)
+                    .await
+                    .map_err(|e| MyError::Other(anyhow::anyhow!(format!("OIDC discovery: {e}"))))
+            })
+            .await?;
+        Ok(CoreClient::from_provider_metadata(
+            provider.clone(),
+            ClientId::new(self.client_id.clone()),
+            self.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new(self.redirect_url.clone()).map_err(|e| MyError::Other(e.into()))?,
+        ))
+    }
+
+    /// Returns where to send the browser to log in, and the value for the
+    /// cookie that ties the login to that browser
+    pub async fn begin_login(&self, link_account: Option<Uuid>) -> Result<(String, String)> {
+        PENDING.retain(|_, pending| pending.started.elapsed() < LOGIN_TIMEOUT);
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = self
+            .client()
+            .await?
+            .authorize_url(
+                AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("profile".to_string()))
+            .add_scope(Scope::new("email".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        let browser = CsrfToken::new_random().secret().clone();
+        PENDING.insert(
+            state.secret().clone(),
+            PendingLogin {
+                browser: browser.clone(),
+                pkce_verifier,
+                nonce,
+                link_account,
+                started: Instant::now(),
+            },
+        );
+        Ok((url.to_string(), browser))
+    }
+
+    /// Swaps the code from the callback for an ID token, and checks it. The
+    /// login has to come back to the browser that started it.
+    pub async fn finish_login(
+        &self,
+        code: String,
+        state: &str,
+        browser: Option<String>,
+    ) -> Result<Identity> {
+        let (_, pending) = PENDING
+            .remove_if(state, |_, pending| {
+                browser.as_deref() == Some(pending.browser.as_str())
+            })
+            .filter(|(_, pending)| pending.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or_else(|| MyError::Unauthorized("Unknown or expired login".to_string()))?;
+        let client = self.client().await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pending.pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| MyError::Unauthorized(format!("Failed to exchange code: {e}")))?;
+        let id_token = token_response.id_token().ok_or_else(|| {
+            MyError::Unauthorized("Identity provider didn't send an ID token".to_string())
+        })?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &pending.nonce)
+            .map_err(|e| MyError::Unauthorized(format!("Invalid ID token: {e}")))?;
+        Ok(Identity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            preferred_username: claims
+                .preferred_username()
+                .map(|username| username.to_string())
+                .or_else(|| claims.email().map(|email| email.to_string())),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            link_account: pending.link_account,
+        })
+    }
+}
+
+/// Hands out a one-time code the frontend can swap for a session on the
+/// account, so session tokens never go in a URL
+pub fn hand_over(account_id: Uuid) -> String {
+    HANDOVERS.retain(|_, (_, started)| started.elapsed() < HANDOVER_TIMEOUT);
+    let code = CsrfToken::new_random().secret().clone();
+    HANDOVERS.insert(code.clone(), (account_id, Instant::now()));
+    code
+}
+
+/// Uses up a code from `hand_over`
+pub fn take_handover(code: &str) -> Result<Uuid> {
+    HANDOVERS
+        .remove(code)
+        .filter(|(_, (_, started))| started.elapsed() < HANDOVER_TIMEOUT)
+        .map(|(_, (account_id, _))| account_id)
+        .ok_or_else(|| MyError::Unauthorized("Unknown or expired login code".to_string()))
+}
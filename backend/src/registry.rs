@@ -0,0 +1,189 @@
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+use warp::ws::Message;
+
+lazy_static! {
+    static ref SESSIONS: DashMap<Uuid, PersonSessions> = DashMap::new();
+    /// Whether only one session per person may hold the camera at once, set
+    /// via `SINGLE_MEDIA_SESSION`
+    static ref SINGLE_MEDIA_SESSION: bool = env::var("SINGLE_MEDIA_SESSION")
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true);
+}
+
+/// All the open connections for one person, e.g. several tabs or devices
+#[derive(Default)]
+struct PersonSessions {
+    senders: HashMap<Uuid, UnboundedSender<Message>>,
+    media: Option<Uuid>,
+}
+
+pub fn register(person_id: Uuid, session_id: Uuid, tx: UnboundedSender<Message>) {
+    SESSIONS
+        .entry(person_id)
+        .or_default()
+        .senders
+        .insert(session_id, tx);
+}
+
+/// Returns true if that was the person's last session
+pub fn deregister(person_id: Uuid, session_id: Uuid) -> bool {
+    let mut last = false;
+    SESSIONS.remove_if_mut(&person_id, |_, sessions| {
+        sessions.senders.remove(&session_id);
+        if sessions.media == Some(session_id) {
+            sessions.media = None;
+        }
+        last = sessions.senders.is_empty();
+        last
+    });
+    last
+}
+
+fn send(person_id: Uuid, tx: &UnboundedSender<Message>, text: &str) -> bool {
+    match tx.send(Message::text(text)) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Failed to queue message for {}: {}", person_id, e);
+            false
+        }
+    }
+}
+
+/// Sends to one particular session
+pub fn send_to_session(person_id: Uuid, session_id: Uuid, text: &str) -> bool {
+    match SESSIONS.get(&person_id) {
+        Some(sessions) => match sessions.senders.get(&session_id) {
+            Some(tx) => send(person_id, tx, text),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// Sends to every session the person has open. Returns false if none of them
+/// could take it.
+pub fn send_to_person(person_id: Uuid, text: &str) -> bool {
+    match SESSIONS.get(&person_id) {
+        Some(sessions) => {
+            // Deliberately not `any`, as every session needs to get it
+            let sent = sessions
+                .senders
+                .values()
+                .filter(|tx| send(person_id, tx, text))
+                .count();
+            sent > 0
+        }
+        None => false,
+    }
+}
+
+/// Sends to whichever session is holding the camera, or to all of them if
+/// none has claimed it
+pub fn send_to_media(person_id: Uuid, text: &str) -> bool {
+    let media = match SESSIONS.get(&person_id) {
+        Some(sessions) => sessions.media,
+        None => return false,
+    };
+    match media {
+        Some(session_id) => send_to_session(person_id, session_id, text),
+        None => send_to_person(person_id, text),
+    }
+}
+
+/// Marks a session as the one holding the camera. Returns the session it
+/// took over from, if policy says there can only be one.
+pub fn claim_media(person_id: Uuid, session_id: Uuid) -> Option<Uuid> {
+    if !*SINGLE_MEDIA_SESSION {
+        return None;
+    }
+    let mut sessions = SESSIONS.get_mut(&person_id)?;
+    let previous = sessions.media.replace(session_id);
+    previous.filter(|previous| *previous != session_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn connect(person_id: Uuid) -> (Uuid, UnboundedReceiver<Message>) {
+        let session_id = Uuid::new_v4();
+        let (tx, rx) = unbounded_channel();
+        register(person_id, session_id, tx);
+        (session_id, rx)
+    }
+
+    fn received(rx: &mut UnboundedReceiver<Message>) -> Vec<String> {
+        let mut texts = vec![];
+        while let Ok(message) = rx.try_recv() {
+            texts.push(message.to_str().unwrap().to_string());
+        }
+        texts
+    }
+
+    #[test]
+    fn media_goes_everywhere_until_claimed() {
+        let person_id = Uuid::new_v4();
+        let (_, mut phone) = connect(person_id);
+        let (_, mut laptop) = connect(person_id);
+        assert!(send_to_media(person_id, "offer"));
+        assert_eq!(received(&mut phone), vec!["offer"]);
+        assert_eq!(received(&mut laptop), vec!["offer"]);
+    }
+
+    #[test]
+    fn claimed_session_gets_the_media() {
+        let person_id = Uuid::new_v4();
+        let (_, mut phone) = connect(person_id);
+        let (laptop_id, mut laptop) = connect(person_id);
+        assert_eq!(claim_media(person_id, laptop_id), None);
+        // Claiming again isn't taking over from anyone
+        assert_eq!(claim_media(person_id, laptop_id), None);
+        assert!(send_to_media(person_id, "offer"));
+        assert!(received(&mut phone).is_empty());
+        assert_eq!(received(&mut laptop), vec!["offer"]);
+        // Everything else still goes to both
+        assert!(send_to_person(person_id, "chat"));
+        assert_eq!(received(&mut phone), vec!["chat"]);
+        assert_eq!(received(&mut laptop), vec!["chat"]);
+    }
+
+    #[test]
+    fn competing_claim_takes_over() {
+        let person_id = Uuid::new_v4();
+        let (phone_id, mut phone) = connect(person_id);
+        let (laptop_id, mut laptop) = connect(person_id);
+        claim_media(person_id, phone_id);
+        assert_eq!(claim_media(person_id, laptop_id), Some(phone_id));
+        assert!(send_to_media(person_id, "offer"));
+        assert!(received(&mut phone).is_empty());
+        assert_eq!(received(&mut laptop), vec!["offer"]);
+    }
+
+    #[test]
+    fn dropping_the_media_session_releases_it() {
+        let person_id = Uuid::new_v4();
+        let (phone_id, mut phone) = connect(person_id);
+        let (laptop_id, _laptop) = connect(person_id);
+        claim_media(person_id, laptop_id);
+        assert!(!deregister(person_id, laptop_id));
+        assert!(send_to_media(person_id, "offer"));
+        assert_eq!(received(&mut phone), vec!["offer"]);
+        // Nobody was holding it any more, so nothing's taken over
+        assert_eq!(claim_media(person_id, phone_id), None);
+        assert!(deregister(person_id, phone_id));
+        assert!(!send_to_media(person_id, "offer"));
+    }
+
+    #[test]
+    fn unknown_people_can_not_claim() {
+        assert_eq!(claim_media(Uuid::new_v4(), Uuid::new_v4()), None);
+        assert!(!send_to_media(Uuid::new_v4(), "offer"));
+    }
+}
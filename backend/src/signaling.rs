@@ -0,0 +1,257 @@
+use crate::error::{MyError, Result};
+use crate::types::Person;
+use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Generous upper bound for a session description. Real offers with a
+/// handful of tracks are a few KB.
+pub const MAX_SDP_LENGTH: usize = 64 * 1024;
+pub const MAX_CANDIDATE_LENGTH: usize = 1024;
+pub const MAX_SDP_MID_LENGTH: usize = 64;
+
+lazy_static! {
+    /// Who `Send` may reach, and who a `DirectMessage` conversation can be
+    /// started with, set via `DIRECT_MESSAGE_SCOPE`
+    pub static ref DIRECT_MESSAGE_SCOPE: RelayScope =
+        RelayScope::from_env("DIRECT_MESSAGE_SCOPE", RelayScope::Table);
+}
+
+/// How close two people need to be before one can relay messages to the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayScope {
+    Table,
+    Pub,
+    Anyone,
+}
+
+impl RelayScope {
+    fn from_env(name: &str, default: RelayScope) -> RelayScope {
+        match env::var(name).as_deref() {
+            Ok("table") => RelayScope::Table,
+            Ok("pub") => RelayScope::Pub,
+            Ok("anyone") => RelayScope::Anyone,
+            Ok(other) => {
+                warn!("Unknown {} value '{}', using {:?}", name, other, default);
+                default
+            }
+            Err(_) => default,
+        }
+    }
+
+    pub fn check(&self, sender: &Person, recipient: &Person) -> Result<()> {
+        let allowed = match self {
+            RelayScope::Table => sender.table_id.is_some() && sender.table_id == recipient.table_id,
+            RelayScope::Pub => sender.pub_id.is_some() && sender.pub_id == recipient.pub_id,
+            RelayScope::Anyone => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(MyError::Forbidden(format!(
+                "{} needs to be at the same {} as you",
+                recipient.id,
+                match self {
+                    RelayScope::Table => "table",
+                    _ => "pub",
+                }
+            )))
+        }
+    }
+}
+
+/// Mirrors the browser's `RTCIceCandidateInit`, so the frontend can pass
+/// `RTCPeerConnection.onicecandidate` results straight through
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IceCandidate {
+    pub candidate: String,
+    pub sdp_mid: Option<String>,
+    #[serde(rename = "sdpMLineIndex")]
+    pub sdp_m_line_index: Option<u16>,
+    pub username_fragment: Option<String>,
+}
+
+pub fn validate_sdp(sdp: &str) -> Result<()> {
+    if sdp.len() > MAX_SDP_LENGTH {
+        return Err(MyError::InvalidInput(format!(
+            "SDP is {} bytes, which is over the limit of {MAX_SDP_LENGTH}",
+            sdp.len()
+        )));
+    }
+    if !sdp.starts_with("v=0") {
+        return Err(MyError::InvalidInput(
+            "SDP must start with a version line".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl IceCandidate {
+    pub fn validate(&self) -> Result<()> {
+        if self.candidate.len() > MAX_CANDIDATE_LENGTH {
+            return Err(MyError::InvalidInput(format!(
+                "ICE candidate is {} bytes, which is over the limit of {MAX_CANDIDATE_LENGTH}",
+                self.candidate.len()
+            )));
+        }
+        // An empty candidate is the "end of candidates" marker
+        if !self.candidate.is_empty() && !self.candidate.starts_with("candidate:") {
+            return Err(MyError::InvalidInput(
+                "ICE candidate must start with 'candidate:'".to_string(),
+            ));
+        }
+        if self.sdp_mid.is_none() && self.sdp_m_line_index.is_none() {
+            return Err(MyError::InvalidInput(
+                "ICE candidate needs one of sdpMid or sdpMLineIndex".to_string(),
+            ));
+        }
+        if let Some(sdp_mid) = &self.sdp_mid {
+            if sdp_mid.len() > MAX_SDP_MID_LENGTH {
+                return Err(MyError::InvalidInput(format!(
+                    "sdpMid is over the limit of {MAX_SDP_MID_LENGTH} bytes"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn person(pub_id: Option<Uuid>, table_id: Option<Uuid>) -> Person {
+        Person {
+            id: Uuid::new_v4(),
+            name: None,
+            pub_id,
+            table_id,
+            account_id: None,
+            last_updated: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn table_scope_needs_the_same_table() {
+        let (pub_id, table_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let sender = person(Some(pub_id), Some(table_id));
+        assert!(RelayScope::Table
+            .check(&sender, &person(Some(pub_id), Some(table_id)))
+            .is_ok());
+        for recipient in [
+            person(Some(pub_id), Some(Uuid::new_v4())),
+            person(Some(pub_id), None),
+            person(Some(Uuid::new_v4()), Some(Uuid::new_v4())),
+        ] {
+            assert!(matches!(
+                RelayScope::Table.check(&sender, &recipient),
+                Err(MyError::Forbidden(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn table_scope_needs_a_table() {
+        let pub_id = Uuid::new_v4();
+        assert!(RelayScope::Table
+            .check(&person(Some(pub_id), None), &person(Some(pub_id), None))
+            .is_err());
+    }
+
+    #[test]
+    fn pub_scope_needs_the_same_pub() {
+        let pub_id = Uuid::new_v4();
+        let sender = person(Some(pub_id), Some(Uuid::new_v4()));
+        assert!(RelayScope::Pub
+            .check(&sender, &person(Some(pub_id), None))
+            .is_ok());
+        assert!(RelayScope::Pub
+            .check(&sender, &person(Some(pub_id), Some(Uuid::new_v4())))
+            .is_ok());
+        assert!(matches!(
+            RelayScope::Pub.check(&sender, &person(Some(Uuid::new_v4()), None)),
+            Err(MyError::Forbidden(_))
+        ));
+        // Being in no pub isn't the same pub
+        assert!(RelayScope::Pub
+            .check(&person(None, None), &person(None, None))
+            .is_err());
+    }
+
+    #[test]
+    fn anyone_scope_crosses_pubs() {
+        assert!(RelayScope::Anyone
+            .check(
+                &person(Some(Uuid::new_v4()), None),
+                &person(Some(Uuid::new_v4()), None)
+            )
+            .is_ok());
+    }
+
+    fn candidate(candidate: &str) -> IceCandidate {
+        IceCandidate {
+            candidate: candidate.to_string(),
+            sdp_mid: Some("0".to_string()),
+            sdp_m_line_index: Some(0),
+            username_fragment: None,
+        }
+    }
+
+    #[test]
+    fn candidates_from_the_browser_are_accepted() {
+        let ice = candidate("candidate:842163049 1 udp 1677729535 192.0.2.1 3478 typ srflx");
+        assert!(ice.validate().is_ok());
+        // End of candidates
+        assert!(candidate("").validate().is_ok());
+    }
+
+    #[test]
+    fn candidates_keep_the_browser_field_names() {
+        let ice: IceCandidate = serde_json::from_str(
+            r#"{"candidate":"candidate:1","sdpMid":"0","sdpMLineIndex":0,"usernameFragment":null}"#,
+        )
+        .unwrap();
+        assert_eq!(ice, candidate("candidate:1"));
+    }
+
+    #[test]
+    fn bad_candidates_are_rejected() {
+        let too_long = format!("candidate:{}", "1".repeat(MAX_CANDIDATE_LENGTH));
+        let no_mid = IceCandidate {
+            sdp_mid: None,
+            sdp_m_line_index: None,
+            ..candidate("candidate:1")
+        };
+        let long_mid = IceCandidate {
+            sdp_mid: Some("0".repeat(MAX_SDP_MID_LENGTH + 1)),
+            ..candidate("candidate:1")
+        };
+        for ice in [candidate("a=foo"), candidate(&too_long), no_mid, long_mid] {
+            assert!(
+                matches!(ice.validate(), Err(MyError::InvalidInput(_))),
+                "{:?} was accepted",
+                ice
+            );
+        }
+    }
+
+    #[test]
+    fn sdp_needs_a_version_line() {
+        assert!(validate_sdp("v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\n").is_ok());
+        assert!(matches!(
+            validate_sdp("o=- 0 0 IN IP4 127.0.0.1\r\n"),
+            Err(MyError::InvalidInput(_))
+        ));
+        assert!(validate_sdp("").is_err());
+    }
+
+    #[test]
+    fn sdp_is_capped() {
+        let sdp = format!("v=0\r\n{}", "a".repeat(MAX_SDP_LENGTH));
+        assert!(matches!(validate_sdp(&sdp), Err(MyError::InvalidInput(_))));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::ErrorCode;
+use crate::signaling::IceCandidate;
 use bb8_postgres::PostgresConnectionManager;
 use chrono::NaiveDateTime;
 use postgres::NoTls;
@@ -10,12 +12,14 @@ pub type DbConnection<'a> = bb8::PooledConnection<'a, PostgresConnectionManager<
 #[derive(Clone)]
 pub struct Client {
     pub id: Uuid,
+    pub session_id: Uuid,
+    pub account_id: Option<Uuid>,
     pub pool: Pool,
 }
 
 impl std::fmt::Debug for Client {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<Client {}>", self.id)
+        write!(f, "<Client {} ({})>", self.id, self.session_id)
     }
 }
 
@@ -23,27 +27,239 @@ impl std::fmt::Debug for Client {
 pub struct Pub {
     pub id: Uuid,
     pub name: String,
+    pub owner_id: Option<Uuid>,
+    pub visibility: PubVisibility,
+    /// Short code for joining, like `jolly-badger-4242`
+    pub code: String,
+    /// Picked by the owner, for `/p/{slug}` links
+    pub slug: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PubWithPeople {
     pub id: Uuid,
     pub name: String,
+    pub owner_id: Option<Uuid>,
+    pub visibility: PubVisibility,
+    pub code: String,
+    pub slug: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
     pub persons: Vec<Uuid>,
 }
 
+/// Who gets to see and walk into a pub
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PubVisibility {
+    /// Listed in the lobby
+    #[default]
+    Public,
+    /// Anyone with the pub id can join, but it isn't listed
+    Unlisted,
+    /// Needs an invite, unless you're staff
+    Private,
+}
+
+/// An invite link to a pub. The link itself is a signed token naming the
+/// invite, which stops working once it expires or is revoked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PubInvite {
+    pub id: Uuid,
+    pub pub_id: Uuid,
+    pub created_by: Uuid,
+    pub expires: NaiveDateTime,
+    pub created: NaiveDateTime,
+}
+
+/// What someone's allowed to do in a pub. Ordered from least to most trusted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PubRole {
+    Patron,
+    BarStaff,
+    Landlord,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PubRoleEntry {
+    pub person_id: Uuid,
+    pub role: PubRole,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    SetRole,
+    DeleteTable,
+    DeletePub,
+    RevokeInvite,
+    UpdatePub,
+    UpdateTable,
+    DeleteMessage,
+    ClaimPub,
+}
+
+/// A moderation action, as kept in the audit log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub pub_id: Uuid,
+    pub actor_id: Uuid,
+    pub action: AuditAction,
+    pub target_id: Option<Uuid>,
+    pub reason: Option<String>,
+    pub details: serde_json::Value,
+    pub created: NaiveDateTime,
+}
+
+/// Where a chat message was said
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind")]
+pub enum ChatScope {
+    Pub { pub_id: Uuid },
+    Table { table_id: Uuid },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub pub_id: Uuid,
+    /// Missing for messages to the whole pub
+    pub table_id: Option<Uuid>,
+    pub author_id: Uuid,
+    /// Empty once the message has been deleted
+    pub content: String,
+    pub created: NaiveDateTime,
+    pub edited: Option<NaiveDateTime>,
+    pub deleted: bool,
+    pub reactions: Vec<ReactionCount>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectMessage {
+    pub id: i64,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    pub content: String,
+    pub created: NaiveDateTime,
+    /// When it reached one of the recipient's sessions
+    pub delivered: Option<NaiveDateTime>,
+    pub read: Option<NaiveDateTime>,
+}
+
+/// Someone's direct messages with one other person
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conversation {
+    pub person_id: Uuid,
+    pub last: DirectMessage,
+    /// Messages from them that haven't been read yet
+    pub unread: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Receipt {
+    Delivered,
+    Read,
+}
+
+/// Something someone should hear about, kept until they've read it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Notification {
+    pub id: i64,
+    pub person_id: Uuid,
+    /// Whoever caused it, if anyone did
+    pub actor_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub kind: NotificationKind,
+    pub created: NaiveDateTime,
+    pub read: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind")]
+pub enum NotificationKind {
+    /// Named with `@name` in a chat message
+    Mention {
+        pub_id: Uuid,
+        table_id: Option<Uuid>,
+        message_id: i64,
+        /// The start of the message, as it was when it was posted
+        excerpt: String,
+    },
+    TableInvite {
+        pub_id: Uuid,
+        table_id: Uuid,
+    },
+    /// Given a seat, either from the queue or by being let in after knocking
+    Seated {
+        pub_id: Uuid,
+        table_id: Uuid,
+    },
+    /// Pub staff did something to us
+    Moderation {
+        pub_id: Uuid,
+        action: AuditAction,
+        reason: Option<String>,
+    },
+}
+
+/// A message matching a search, with the matching words picked out of it in
+/// «guillemets»
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatSearchHit {
+    pub message: ChatMessage,
+    pub snippet: String,
+}
+
+/// What a chat message said between two edits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessageVersion {
+    pub content: String,
+    pub written: NaiveDateTime,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PubTable {
     pub id: Uuid,
     pub name: String,
     pub pub_id: Uuid,
+    pub max_seats: Option<i32>,
+    pub access: TableAccess,
+    /// What's being talked about, set by whoever's sat there
+    pub topic: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Who can sit at a table. Anyone invited can get into any of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableAccess {
+    #[default]
+    Open,
+    /// Invite only
+    Locked,
+    Password,
+    /// Ask the people sat there to be let in
+    Knock,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TableWithPeople {
     pub id: Uuid,
     pub name: String,
     pub pub_id: Uuid,
+    pub max_seats: Option<i32>,
+    pub access: TableAccess,
+    pub topic: Option<String>,
     pub persons: Vec<Uuid>,
 }
 
@@ -53,36 +269,516 @@ pub struct Person {
     pub name: Option<String>,
     pub pub_id: Option<Uuid>,
     pub table_id: Option<Uuid>,
+    pub account_id: Option<Uuid>,
     pub last_updated: NaiveDateTime,
 }
 
+/// Someone we've blocked
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Block {
+    pub person_id: Uuid,
+    /// Whether they're also kept from sitting at any table we're at
+    pub keep_off_tables: bool,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub preferences: serde_json::Value,
+}
+
+/// A password or token sent by a client. Kept out of `Debug` output, so it can't
+/// end up in the logs.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(pub String);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "kind")]
 pub enum Command {
     ListPubs,
-    SetName { name: String },
-    GetPerson { user_id: Uuid },
-    CreatePub { name: String },
+    SubscribeLobby,
+    UnsubscribeLobby,
+    SetName {
+        name: String,
+    },
+    GetPerson {
+        user_id: Uuid,
+    },
+    CreatePub {
+        name: String,
+        visibility: Option<PubVisibility>,
+        slug: Option<String>,
+    },
     LeavePub,
-    JoinPub { pub_id: Uuid },
-    DeletePub { pub_id: Uuid },
-    CreateTable { pub_id: Uuid, name: String },
-    ListTables { pub_id: Uuid },
-    JoinTable { table_id: Uuid },
-    DeleteTable { table_id: Uuid },
+    JoinPub {
+        pub_id: Uuid,
+    },
+    JoinPubByInvite {
+        token: Secret,
+    },
+    JoinPubByCode {
+        code: String,
+    },
+    CreatePubInvite {
+        pub_id: Uuid,
+        /// How long the invite lasts for, in seconds
+        ttl: Option<u64>,
+    },
+    ListPubInvites {
+        pub_id: Uuid,
+    },
+    RevokePubInvite {
+        pub_id: Uuid,
+        invite_id: Uuid,
+        reason: Option<String>,
+    },
+    DeletePub {
+        pub_id: Uuid,
+        reason: Option<String>,
+    },
+    /// Only changes what's given. Empty descriptions clear them.
+    UpdatePub {
+        pub_id: Uuid,
+        name: Option<String>,
+        description: Option<String>,
+        tags: Option<Vec<String>>,
+    },
+    /// Pubs from before owners were tracked have no landlord, so the first
+    /// account holder in one to claim it becomes its owner
+    ClaimPub {
+        pub_id: Uuid,
+    },
+    CreateTable {
+        pub_id: Uuid,
+        name: String,
+        /// No limit if missing
+        max_seats: Option<i32>,
+        access: Option<TableAccess>,
+        /// Needed for `TableAccess::Password` tables
+        password: Option<Secret>,
+    },
+    ListTables {
+        pub_id: Uuid,
+    },
+    JoinTable {
+        table_id: Uuid,
+        password: Option<Secret>,
+    },
+    DeleteTable {
+        table_id: Uuid,
+        reason: Option<String>,
+    },
+    /// Only changes what's given. Empty topics clear them.
+    UpdateTable {
+        table_id: Uuid,
+        name: Option<String>,
+        topic: Option<String>,
+    },
     LeaveTable,
-    Send { user_id: Uuid, content: String },
+    Send {
+        user_id: Uuid,
+        content: String,
+    },
+    Offer {
+        user_id: Uuid,
+        sdp: String,
+    },
+    Answer {
+        user_id: Uuid,
+        sdp: String,
+    },
+    IceCandidate {
+        user_id: Uuid,
+        candidate: IceCandidate,
+    },
+    Renegotiate {
+        user_id: Uuid,
+    },
+    Hangup {
+        user_id: Uuid,
+    },
+    ClaimMedia,
+    GetAccount,
+    SetPreferences {
+        preferences: serde_json::Value,
+    },
+    ListRoles {
+        pub_id: Uuid,
+    },
+    SetRole {
+        pub_id: Uuid,
+        user_id: Uuid,
+        role: PubRole,
+    },
+    Kick {
+        user_id: Uuid,
+        reason: Option<String>,
+    },
+    Ban {
+        user_id: Uuid,
+        /// In seconds, up to a year. Bans without one last until they're lifted.
+        duration: Option<u64>,
+        reason: Option<String>,
+    },
+    Unban {
+        user_id: Uuid,
+        reason: Option<String>,
+    },
+    Mute {
+        user_id: Uuid,
+        reason: Option<String>,
+    },
+    Unmute {
+        user_id: Uuid,
+        reason: Option<String>,
+    },
+    ListAuditLog {
+        pub_id: Uuid,
+        /// Only show events from before this event id, for paging back
+        before: Option<i64>,
+        limit: Option<i64>,
+    },
+    QueueForTable {
+        table_id: Uuid,
+        password: Option<Secret>,
+    },
+    InviteToTable {
+        table_id: Uuid,
+        user_id: Uuid,
+    },
+    KnockTable {
+        table_id: Uuid,
+    },
+    Admit {
+        table_id: Uuid,
+        user_id: Uuid,
+    },
+    Deny {
+        table_id: Uuid,
+        user_id: Uuid,
+    },
+    LeaveQueue,
+    ChatMessage {
+        scope: ChatScope,
+        content: String,
+    },
+    ChatHistory {
+        scope: ChatScope,
+        /// Only show messages from before this message id, for paging back
+        before: Option<i64>,
+        limit: Option<i64>,
+    },
+    EditMessage {
+        message_id: i64,
+        content: String,
+    },
+    DeleteMessage {
+        message_id: i64,
+        reason: Option<String>,
+    },
+    /// Reacting with the same emoji again takes it back
+    React {
+        message_id: i64,
+        emoji: String,
+    },
+    /// For moderators, to see what a message said before it was edited or
+    /// deleted
+    ListMessageVersions {
+        message_id: i64,
+    },
+    /// Searches the chat for the whole pub, and for any tables in it that
+    /// anyone could sit at, along with the searcher's own table
+    SearchMessages {
+        pub_id: Uuid,
+        query: String,
+        limit: Option<i64>,
+    },
+    /// Kept until the recipient reads it, unlike `Send`. Starting a
+    /// conversation needs the recipient to be within `DIRECT_MESSAGE_SCOPE`,
+    /// but either side can reply from anywhere after that.
+    DirectMessage {
+        user_id: Uuid,
+        content: String,
+    },
+    Inbox,
+    DirectMessageHistory {
+        user_id: Uuid,
+        /// Only show messages from before this message id, for paging back
+        before: Option<i64>,
+        limit: Option<i64>,
+    },
+    /// Marks everything from them up to and including `up_to` as read
+    MarkDirectMessagesRead {
+        user_id: Uuid,
+        up_to: i64,
+    },
+    ListNotifications {
+        /// Only show notifications from before this one, for paging back
+        before: Option<i64>,
+        limit: Option<i64>,
+    },
+    /// Marks all our notifications up to and including `up_to` as read
+    MarkRead {
+        up_to: i64,
+    },
+    /// Stops either of us reaching the other with `Send` or direct messages,
+    /// and keeps their chat and mentions from reaching us. Blocking someone
+    /// again changes `keep_off_tables`.
+    Block {
+        user_id: Uuid,
+        /// Also stops them sitting down at any table we're at
+        keep_off_tables: Option<bool>,
+    },
+    Unblock {
+        user_id: Uuid,
+    },
+    ListBlocks,
     Ping,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "kind")]
 pub enum Response {
-    CreatePub { data: PubWithPeople },
-    Pubs { list: Vec<PubWithPeople> },
-    CreateTable { data: TableWithPeople },
-    Tables { list: Vec<TableWithPeople> },
-    Person { data: Person },
-    Data { author: Uuid, content: String },
+    CreatePub {
+        data: PubWithPeople,
+    },
+    Pubs {
+        list: Vec<PubWithPeople>,
+    },
+    /// The pub we've just walked into, which might not be in the lobby's list
+    Pub {
+        data: PubWithPeople,
+    },
+    CreateTable {
+        data: TableWithPeople,
+    },
+    Tables {
+        list: Vec<TableWithPeople>,
+    },
+    Person {
+        data: Person,
+    },
+    Account {
+        data: Account,
+    },
+    Data {
+        author: Uuid,
+        content: String,
+    },
+    Offer {
+        author: Uuid,
+        sdp: String,
+    },
+    Answer {
+        author: Uuid,
+        sdp: String,
+    },
+    IceCandidate {
+        author: Uuid,
+        candidate: IceCandidate,
+    },
+    Renegotiate {
+        author: Uuid,
+    },
+    Hangup {
+        author: Uuid,
+    },
     Pong,
+    Session {
+        session_id: Uuid,
+    },
+    SessionReplaced {
+        session_id: Uuid,
+    },
+    PersonJoinedPub {
+        pub_id: Uuid,
+        data: Person,
+    },
+    PersonLeftPub {
+        pub_id: Uuid,
+        person_id: Uuid,
+    },
+    PersonJoinedTable {
+        table_id: Uuid,
+        person_id: Uuid,
+    },
+    PersonLeftTable {
+        table_id: Uuid,
+        person_id: Uuid,
+    },
+    PubCreated {
+        data: PubWithPeople,
+    },
+    PubDeleted {
+        pub_id: Uuid,
+    },
+    PubUpdated {
+        data: PubWithPeople,
+    },
+    PubOccupancy {
+        pub_id: Uuid,
+        persons: Vec<Uuid>,
+    },
+    TableCreated {
+        data: TableWithPeople,
+    },
+    TableDeleted {
+        pub_id: Uuid,
+        table_id: Uuid,
+    },
+    TableUpdated {
+        data: TableWithPeople,
+    },
+    Roles {
+        pub_id: Uuid,
+        list: Vec<PubRoleEntry>,
+    },
+    PubInvite {
+        data: PubInvite,
+        token: String,
+    },
+    PubInvites {
+        pub_id: Uuid,
+        list: Vec<PubInvite>,
+    },
+    RoleChanged {
+        pub_id: Uuid,
+        person_id: Uuid,
+        role: PubRole,
+    },
+    Kicked {
+        pub_id: Uuid,
+        by: Uuid,
+    },
+    Banned {
+        pub_id: Uuid,
+        by: Uuid,
+        expires: Option<NaiveDateTime>,
+    },
+    Unbanned {
+        pub_id: Uuid,
+    },
+    Muted {
+        pub_id: Uuid,
+        by: Uuid,
+    },
+    Unmuted {
+        pub_id: Uuid,
+    },
+    /// We've been given a seat after waiting in the queue for it
+    Seated {
+        table_id: Uuid,
+    },
+    /// Sent to everyone in a queue whenever it changes. Positions start at 1.
+    QueuePosition {
+        table_id: Uuid,
+        position: usize,
+        length: usize,
+    },
+    LeftQueue {
+        table_id: Uuid,
+    },
+    TableInvite {
+        table_id: Uuid,
+        by: Uuid,
+    },
+    /// Someone's asking to be let into our table
+    Knock {
+        table_id: Uuid,
+        person_id: Uuid,
+    },
+    Admitted {
+        table_id: Uuid,
+        by: Uuid,
+    },
+    Denied {
+        table_id: Uuid,
+        by: Uuid,
+    },
+    ChatMessage {
+        data: ChatMessage,
+    },
+    /// Newest first
+    ChatHistory {
+        scope: ChatScope,
+        list: Vec<ChatMessage>,
+    },
+    /// Sent when a message is edited, deleted or reacted to
+    ChatMessageUpdated {
+        data: ChatMessage,
+    },
+    /// Oldest first
+    MessageVersions {
+        message_id: i64,
+        list: Vec<ChatMessageVersion>,
+    },
+    /// Sent to every session of both the sender and the recipient
+    DirectMessage {
+        data: DirectMessage,
+    },
+    /// Most recent conversation first
+    Inbox {
+        list: Vec<Conversation>,
+        /// Across all conversations
+        unread: i64,
+    },
+    /// Newest first
+    DirectMessageHistory {
+        user_id: Uuid,
+        list: Vec<DirectMessage>,
+    },
+    /// Every message from the sender to the recipient up to and including
+    /// `up_to` has been delivered or read. Goes to both of them, so the
+    /// recipient's other sessions can keep their unread counts up to date.
+    DirectMessageReceipt {
+        sender_id: Uuid,
+        recipient_id: Uuid,
+        up_to: i64,
+        receipt: Receipt,
+        at: NaiveDateTime,
+    },
+    /// Pushed to every session of the person it's for, as it happens
+    Notification {
+        data: Notification,
+    },
+    /// Newest first
+    Notifications {
+        list: Vec<Notification>,
+        unread: i64,
+    },
+    /// Sent to all our sessions, so they can keep their unread counts in step
+    NotificationsRead {
+        up_to: i64,
+        unread: i64,
+    },
+    /// Sent to all our sessions whenever it changes
+    Blocks {
+        list: Vec<Block>,
+    },
+    /// Best matches first
+    SearchResults {
+        pub_id: Uuid,
+        query: String,
+        list: Vec<ChatSearchHit>,
+    },
+    AuditLog {
+        pub_id: Uuid,
+        /// Newest first
+        list: Vec<AuditEvent>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        command: Option<String>,
+    },
 }
@@ -0,0 +1,106 @@
+import { useState } from "react";
+import { linkSsoLogin, login, logout, register } from "./Session";
+import { useUIStore } from "./Store";
+
+export function Account() {
+  const accountId = useUIStore((s) => s.accountId);
+  const [username, setUsername] = useState("");
+  const [password, setPassword] = useState("");
+  const [error, setError] = useState<string | null>(null);
+
+  const sso =
+    accountId === null ? (
+      <a id="sso-login" className="btn btn-secondary" href="/api/oidc/login">
+        Log in with SSO
+      </a>
+    ) : (
+      <button
+        id="sso-login"
+        className="btn btn-secondary"
+        onClick={(evt) => {
+          linkSsoLogin().then(setError);
+          evt.preventDefault();
+        }}
+      >
+        Link SSO login
+      </button>
+    );
+
+  if (accountId !== null) {
+    return (
+      <div>
+        <button
+          id="logout"
+          className="btn btn-secondary"
+          onClick={(evt) => {
+            logout();
+            evt.preventDefault();
+          }}
+        >
+          Log out
+        </button>
+        <span>&nbsp;</span>
+        {sso}
+        {error !== null && <div className="text-danger">{error}</div>}
+      </div>
+    );
+  }
+
+  const submit = (action: typeof login) => {
+    action(username, password).then((message) => {
+      setError(message);
+      if (message === null) {
+        setPassword("");
+      }
+    });
+  };
+
+  return (
+    <form>
+      <div className="form-group">
+        <input
+          type="text"
+          className="form-control"
+          id="username"
+          placeholder="Username"
+          value={username}
+          onChange={(evt) => setUsername(evt.target.value)}
+        />
+        <input
+          type="password"
+          className="form-control"
+          id="password"
+          placeholder="Password"
+          value={password}
+          onChange={(evt) => setPassword(evt.target.value)}
+        />
+      </div>
+      {error !== null && <div className="text-danger">{error}</div>}
+      <button
+        id="login"
+        type="button"
+        className="btn btn-primary"
+        onClick={(evt) => {
+          submit(login);
+          evt.preventDefault();
+        }}
+      >
+        Log in
+      </button>
+      <span>&nbsp;</span>
+      <button
+        id="register"
+        type="button"
+        className="btn btn-secondary"
+        onClick={(evt) => {
+          submit(register);
+          evt.preventDefault();
+        }}
+      >
+        Register
+      </button>
+      <span>&nbsp;</span>
+      {sso}
+    </form>
+  );
+}
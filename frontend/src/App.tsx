@@ -12,6 +12,7 @@ import { Pub } from "./Pub";
 import { useEffect } from "react";
 import { useMediaStreamWrapper } from "./Video";
 import { Table } from "./Table";
+import { Invite, JoinByCode } from "./Invite";
 import { ping } from "./commands";
 import { useWebsocket } from "./Websocket";
 
@@ -68,6 +69,8 @@ function App() {
         { path: "Home", element: <Home /> },
         { path: "Pub", element: <Pub /> },
         { path: "Table", element: <Table /> },
+        { path: "Invite/:token", element: <Invite /> },
+        { path: "Join/:code", element: <JoinByCode /> },
         {
           path: "about",
           element: <About />,
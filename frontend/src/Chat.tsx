@@ -0,0 +1,227 @@
+import { useEffect, useState } from "react";
+import {
+  block,
+  chatHistory,
+  chatMessage,
+  deleteMessage,
+  editMessage,
+  react,
+  searchMessages,
+} from "./commands";
+import { ChatMessage, ChatScope } from "./Data";
+import { useUIStore } from "./Store";
+import { useWebsocket } from "./Websocket";
+
+function ChatLine(props: { message: ChatMessage }) {
+  const { message } = props;
+  const persons = useUIStore((s) => s.persons);
+  const peerId = useUIStore((s) => s.peerId);
+  const myRole = useUIStore((s) => s.myRole());
+  const websocket = useWebsocket();
+  const author = persons[message.author_id]?.name ?? "Someone";
+  if (message.deleted) {
+    return (
+      <li className="chatMessage text-muted">
+        <i>{author}'s message was deleted</i>
+      </li>
+    );
+  }
+  const mine = message.author_id == peerId;
+  return (
+    <li className="chatMessage">
+      <b>{author}:</b> {message.content}
+      {message.edited !== null && (
+        <small className="text-muted" title={message.edited}>
+          {" "}
+          (edited)
+        </small>
+      )}{" "}
+      {message.reactions.map((r) => (
+        <button
+          key={r.emoji}
+          className="btn btn-sm btn-light"
+          onClick={() => react(websocket, message.id, r.emoji)}
+        >
+          {r.emoji} {r.count}
+        </button>
+      ))}
+      {!message.reactions.some((r) => r.emoji == "👍") && (
+        <button
+          className="btn btn-sm btn-light"
+          onClick={() => react(websocket, message.id, "👍")}
+        >
+          👍
+        </button>
+      )}
+      {!mine && (
+        <button
+          className="btn btn-sm btn-link"
+          onClick={() =>
+            useUIStore.setState((s) => ({
+              ...s,
+              conversationWith: message.author_id,
+            }))
+          }
+        >
+          Message
+        </button>
+      )}
+      {!mine && (
+        <button
+          className="btn btn-sm btn-link"
+          onClick={() => {
+            if (window.confirm(`Block ${author}?`)) {
+              block(
+                websocket,
+                message.author_id,
+                window.confirm(
+                  `Keep ${author} away from any table you're sat at too?`
+                )
+              );
+            }
+          }}
+        >
+          Block
+        </button>
+      )}
+      {mine && (
+        <button
+          className="btn btn-sm btn-link"
+          onClick={() => {
+            const content = window.prompt("Edit message", message.content);
+            if (content !== null && content.trim() != "") {
+              editMessage(websocket, message.id, content);
+            }
+          }}
+        >
+          Edit
+        </button>
+      )}
+      {(mine || myRole != "Patron") && (
+        <button
+          className="btn btn-sm btn-link"
+          onClick={() => {
+            if (mine) {
+              deleteMessage(websocket, message.id);
+            } else {
+              const reason = window.prompt("Reason for deleting (optional)");
+              if (reason !== null) {
+                deleteMessage(websocket, message.id, reason || null);
+              }
+            }
+          }}
+        >
+          Delete
+        </button>
+      )}
+    </li>
+  );
+}
+
+// Picks out the «matching» words in a search snippet
+function Snippet(props: { snippet: string }) {
+  const parts = props.snippet.split(/«|»/);
+  return (
+    <>
+      {parts.map((part, i) =>
+        i % 2 == 1 ? <mark key={i}>{part}</mark> : part
+      )}
+    </>
+  );
+}
+
+export function ChatSearch(props: { pubId: string }) {
+  const { pubId } = props;
+  const [query, setQuery] = useState("");
+  const results = useUIStore((s) => s.searchResults);
+  const persons = useUIStore((s) => s.persons);
+  const websocket = useWebsocket();
+  return (
+    <div className="chatSearch">
+      <form
+        className="form-inline"
+        onSubmit={(evt) => {
+          if (query.trim() != "") {
+            searchMessages(websocket, pubId, query);
+          }
+          evt.preventDefault();
+        }}
+      >
+        <input
+          type="search"
+          className="form-control"
+          placeholder="Search the chat"
+          value={query}
+          onChange={(evt) => setQuery(evt.target.value)}
+        />
+        <button type="submit" className="btn btn-secondary">
+          Search
+        </button>
+      </form>
+      {results !== null && results.pub_id == pubId && (
+        <ul className="list-unstyled">
+          {results.list.length == 0 && (
+            <li>Nothing found for {results.query}</li>
+          )}
+          {results.list.map((hit) => (
+            <li key={hit.message.id} title={hit.message.created}>
+              <b>{persons[hit.message.author_id]?.name ?? "Someone"}:</b>{" "}
+              <Snippet snippet={hit.snippet} />
+            </li>
+          ))}
+        </ul>
+      )}
+    </div>
+  );
+}
+
+export function Chat(props: { scope: ChatScope }) {
+  const { scope } = props;
+  const [content, setContent] = useState("");
+  const chat = useUIStore((s) => s.chat);
+  const blocks = useUIStore((s) => s.blocks);
+  const websocket = useWebsocket();
+  const scopeId = scope.kind == "Pub" ? scope.pub_id : scope.table_id;
+  useEffect(() => {
+    chatHistory(websocket, scope);
+  }, [scopeId]);
+  const messages = chat.filter(
+    (m) =>
+      (scope.kind == "Pub"
+        ? m.pub_id == scope.pub_id && m.table_id === null
+        : m.table_id == scope.table_id) &&
+      !blocks.some((b) => b.person_id == m.author_id)
+  );
+  return (
+    <div className="chat">
+      <ul className="list-unstyled">
+        {messages.map((m) => (
+          <ChatLine key={m.id} message={m} />
+        ))}
+      </ul>
+      <form
+        className="form-inline"
+        onSubmit={(evt) => {
+          if (content.trim() != "") {
+            chatMessage(websocket, scope, content);
+            setContent("");
+          }
+          evt.preventDefault();
+        }}
+      >
+        <input
+          type="text"
+          className="form-control"
+          placeholder={
+            scope.kind == "Pub" ? "Say to the pub" : "Say to the table"
+          }
+          value={content}
+          onChange={(evt) => setContent(evt.target.value)}
+        />
+        <button type="submit" className="btn btn-primary">
+          Send
+        </button>
+      </form>
+    </div>
+  );
+}
@@ -1,5 +1,7 @@
 import { useEffect, useState } from "react";
 import { Outlet, useLocation, useNavigate } from "react-router-dom";
+import { DirectMessages } from "./DirectMessages";
+import { Notifications } from "./Notifications";
 import { useUIStore } from "./Store";
 
 function Clock() {
@@ -25,7 +27,12 @@ function Core() {
   useEffect(() => {
     console.log("pathname", location.pathname);
     if (me === null || me.pub_id === null) {
-      if (location.pathname !== "/Home") {
+      // Invite and code links take us into the pub themselves
+      if (
+        location.pathname !== "/Home" &&
+        !location.pathname.startsWith("/Invite/") &&
+        !location.pathname.startsWith("/Join/")
+      ) {
         navigate("/Home");
       }
     } else if (me !== null && me.pub_id !== null) {
@@ -78,6 +85,8 @@ function Core() {
       </nav>
       <main role="main" className="container-fluid">
         <Outlet />
+        <Notifications />
+        <DirectMessages />
       </main>
     </div>
   );
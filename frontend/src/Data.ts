@@ -6,14 +6,145 @@ export interface Person {
   last_updated: string;
 }
 
+export type PubVisibility = "Public" | "Unlisted" | "Private";
+
 export interface Pub {
   id: string;
   name: string;
+  owner_id: string | null;
+  visibility: PubVisibility;
+  code: string;
+  slug: string | null;
+  description: string | null;
+  tags: string[];
   persons: string[];
 }
 
+export interface PubInvite {
+  id: string;
+  pub_id: string;
+  created_by: string;
+  expires: string;
+  created: string;
+}
+
+export type PubRole = "Patron" | "BarStaff" | "Landlord";
+
+export interface AuditEvent {
+  id: number;
+  pub_id: string;
+  actor_id: string;
+  action:
+    | "Kick"
+    | "Ban"
+    | "Unban"
+    | "Mute"
+    | "Unmute"
+    | "SetRole"
+    | "DeleteTable"
+    | "DeletePub"
+    | "RevokeInvite"
+    | "UpdatePub"
+    | "UpdateTable"
+    | "DeleteMessage"
+    | "ClaimPub";
+  target_id: string | null;
+  reason: string | null;
+  details: object;
+  created: string;
+}
+
+export type NotificationKind =
+  | {
+      kind: "Mention";
+      pub_id: string;
+      table_id: string | null;
+      message_id: number;
+      excerpt: string;
+    }
+  | { kind: "TableInvite"; pub_id: string; table_id: string }
+  | { kind: "Seated"; pub_id: string; table_id: string }
+  | {
+      kind: "Moderation";
+      pub_id: string;
+      action: AuditEvent["action"];
+      reason: string | null;
+    };
+
+export type Notification = NotificationKind & {
+  id: number;
+  person_id: string;
+  actor_id: string | null;
+  created: string;
+  read: string | null;
+};
+
+export type ChatScope =
+  | { kind: "Pub"; pub_id: string }
+  | { kind: "Table"; table_id: string };
+
+export interface ChatMessage {
+  id: number;
+  pub_id: string;
+  table_id: string | null;
+  author_id: string;
+  // Empty once deleted
+  content: string;
+  created: string;
+  edited: string | null;
+  deleted: boolean;
+  reactions: { emoji: string; count: number }[];
+}
+
+// The snippet has the matching words in «guillemets»
+export interface ChatSearchHit {
+  message: ChatMessage;
+  snippet: string;
+}
+
+export interface DirectMessage {
+  id: number;
+  sender_id: string;
+  recipient_id: string;
+  content: string;
+  created: string;
+  delivered: string | null;
+  read: string | null;
+}
+
+export interface Conversation {
+  person_id: string;
+  last: DirectMessage;
+  // Messages from them we've not read yet
+  unread: number;
+}
+
+export type Receipt = "Delivered" | "Read";
+
+export interface ChatMessageVersion {
+  content: string;
+  written: string;
+}
+
+export interface Block {
+  person_id: string;
+  // Also kept from sitting at any table we're at
+  keep_off_tables: boolean;
+  created: string;
+}
+
+export interface PubRoleEntry {
+  person_id: string;
+  role: PubRole;
+}
+
+export type TableAccess = "Open" | "Locked" | "Password" | "Knock";
+
 export interface Table {
   id: string;
   name: string;
+  max_seats: number | null;
+  access: TableAccess;
+  topic: string | null;
   persons: string[];
 }
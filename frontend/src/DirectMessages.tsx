@@ -0,0 +1,152 @@
+import { useEffect, useState } from "react";
+import {
+  directMessage,
+  directMessageHistory,
+  inbox,
+  listBlocks,
+  markDirectMessagesRead,
+  unblock,
+} from "./commands";
+import { DirectMessage } from "./Data";
+import { useUIStore } from "./Store";
+import { useWebsocket } from "./Websocket";
+
+function status(message: DirectMessage): string {
+  if (message.read !== null) {
+    return "Read";
+  } else if (message.delivered !== null) {
+    return "Delivered";
+  }
+  return "Sent";
+}
+
+function Conversation(props: { personId: string }) {
+  const { personId } = props;
+  const [content, setContent] = useState("");
+  const peerId = useUIStore((s) => s.peerId);
+  const persons = useUIStore((s) => s.persons);
+  const directMessages = useUIStore((s) => s.directMessages);
+  const websocket = useWebsocket();
+  const messages = directMessages.filter(
+    (m) => m.sender_id == personId || m.recipient_id == personId
+  );
+  const name = persons[personId]?.name ?? "Someone";
+  useEffect(() => {
+    directMessageHistory(websocket, personId);
+  }, [personId]);
+  // Anything from them is read once it's on screen
+  const lastUnread = messages
+    .filter((m) => m.sender_id == personId && m.read === null)
+    .pop();
+  useEffect(() => {
+    if (lastUnread !== undefined) {
+      markDirectMessagesRead(websocket, personId, lastUnread.id);
+    }
+  }, [lastUnread?.id]);
+  const lastMine = messages.filter((m) => m.sender_id == peerId).pop();
+  return (
+    <div className="conversation">
+      <h5>
+        {name}{" "}
+        <button
+          className="btn btn-sm btn-link"
+          onClick={() =>
+            useUIStore.setState((s) => ({ ...s, conversationWith: null }))
+          }
+        >
+          Close
+        </button>
+      </h5>
+      <ul className="list-unstyled">
+        {messages.map((m) => (
+          <li key={m.id} title={m.created}>
+            <b>{m.sender_id == peerId ? "You" : name}:</b> {m.content}
+            {m.id == lastMine?.id && (
+              <small className="text-muted"> ({status(m)})</small>
+            )}
+          </li>
+        ))}
+      </ul>
+      <form
+        className="form-inline"
+        onSubmit={(evt) => {
+          if (content.trim() != "") {
+            directMessage(websocket, personId, content);
+            setContent("");
+          }
+          evt.preventDefault();
+        }}
+      >
+        <input
+          type="text"
+          className="form-control"
+          placeholder={`Message ${name}`}
+          value={content}
+          onChange={(evt) => setContent(evt.target.value)}
+        />
+        <button type="submit" className="btn btn-primary">
+          Send
+        </button>
+      </form>
+    </div>
+  );
+}
+
+export function DirectMessages() {
+  const conversations = useUIStore((s) => s.inbox);
+  const conversationWith = useUIStore((s) => s.conversationWith);
+  const persons = useUIStore((s) => s.persons);
+  const blocks = useUIStore((s) => s.blocks);
+  const websocket = useWebsocket();
+  useEffect(() => {
+    inbox(websocket);
+    listBlocks(websocket);
+  }, []);
+  const unread = conversations.reduce((total, c) => total + c.unread, 0);
+  return (
+    <div className="directMessages">
+      <h4>Messages{unread > 0 && ` (${unread} unread)`}</h4>
+      <ul className="list-unstyled">
+        {conversations.map((c) => (
+          <li key={c.person_id}>
+            <button
+              className="btn btn-sm btn-link"
+              onClick={() =>
+                useUIStore.setState((s) => ({
+                  ...s,
+                  conversationWith: c.person_id,
+                }))
+              }
+            >
+              {persons[c.person_id]?.name ?? "Someone"}
+              {c.unread > 0 && ` (${c.unread})`}
+            </button>{" "}
+            <small className="text-muted">{c.last.content}</small>
+          </li>
+        ))}
+      </ul>
+      {conversationWith !== null && (
+        <Conversation personId={conversationWith} />
+      )}
+      {blocks.length > 0 && (
+        <>
+          <h5>Blocked</h5>
+          <ul className="list-unstyled">
+            {blocks.map((b) => (
+              <li key={b.person_id}>
+                {persons[b.person_id]?.name ?? "Someone"}
+                {b.keep_off_tables && " (kept away from your table)"}{" "}
+                <button
+                  className="btn btn-sm btn-link"
+                  onClick={() => unblock(websocket, b.person_id)}
+                >
+                  Unblock
+                </button>
+              </li>
+            ))}
+          </ul>
+        </>
+      )}
+    </div>
+  );
+}
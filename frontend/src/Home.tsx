@@ -1,15 +1,28 @@
 import { useState } from "react";
-import { createPub, deletePub, joinPub, listPubs } from "./commands";
+import { PubVisibility } from "./Data";
+import { Account } from "./Account";
+import {
+  createPub,
+  deletePub,
+  joinPub,
+  joinPubByCode,
+  listPubs,
+} from "./commands";
 import { useUIStore } from "./Store";
 import { useWebsocket } from "./Websocket";
 
 export default function Home() {
   const [pubName, setPubName] = useState("");
+  const [visibility, setVisibility] = useState<PubVisibility>("Public");
+  const [slug, setSlug] = useState("");
+  const [code, setCode] = useState("");
   const pubs = useUIStore((s) => s.pubs);
+  const peerId = useUIStore((s) => s.peerId);
   const websocket = useWebsocket();
   return (
     <div>
       <h1>Tavern</h1>
+      <Account />
       <input
         type="button"
         className="btn btn-secondary"
@@ -19,6 +32,26 @@ export default function Home() {
           evt.preventDefault();
         }}
       ></input>
+      <form className="form-inline">
+        <input
+          type="text"
+          className="form-control"
+          id="pubCode"
+          placeholder="Pub code, e.g. jolly-badger-4242"
+          value={code}
+          onChange={(evt) => setCode(evt.target.value)}
+        />
+        <button
+          type="button"
+          className="btn btn-primary"
+          onClick={(evt) => {
+            joinPubByCode(websocket, code);
+            evt.preventDefault();
+          }}
+        >
+          Join with code
+        </button>
+      </form>
       <div>Pubs</div>
       <ul>
         {pubs.map((pub) => (
@@ -35,7 +68,7 @@ export default function Home() {
               Join
             </button>
             <span>&nbsp;</span>
-            {pub.persons.length == 0 && (
+            {pub.persons.length == 0 && pub.owner_id == peerId && (
               <button
                 className="btn btn-danger deletePub"
                 onClick={(evt) => {
@@ -60,13 +93,33 @@ export default function Home() {
             value={pubName}
             onChange={(evt) => setPubName(evt.target.value)}
           />
+          <select
+            className="form-control"
+            id="pubVisibility"
+            value={visibility}
+            onChange={(evt) =>
+              setVisibility(evt.target.value as PubVisibility)
+            }
+          >
+            <option value="Public">Listed in the lobby</option>
+            <option value="Unlisted">Unlisted</option>
+            <option value="Private">Invite only</option>
+          </select>
+          <input
+            type="text"
+            className="form-control"
+            id="pubSlug"
+            placeholder="Link name (optional)"
+            value={slug}
+            onChange={(evt) => setSlug(evt.target.value)}
+          />
         </div>
         <button
           id="createPub"
           type="button"
           className="btn btn-primary"
           onClick={(evt) => {
-            createPub(websocket, pubName);
+            createPub(websocket, pubName, visibility, slug || null);
             evt.preventDefault();
           }}
         >
@@ -0,0 +1,38 @@
+import { useEffect } from "react";
+import { useParams } from "react-router-dom";
+import { joinPubByCode, joinPubByInvite } from "./commands";
+import { useWebsocket } from "./Websocket";
+
+// Landing pages for invite and code links. Once we're in the pub, Core takes
+// us there.
+export function Invite() {
+  const { token } = useParams();
+  const websocket = useWebsocket();
+  useEffect(() => {
+    if (token !== undefined) {
+      joinPubByInvite(websocket, token);
+    }
+  }, [token]);
+  return (
+    <div>
+      <h1>Joining pub...</h1>
+      <a href="#/Home">Back to the lobby</a>
+    </div>
+  );
+}
+
+export function JoinByCode() {
+  const { code } = useParams();
+  const websocket = useWebsocket();
+  useEffect(() => {
+    if (code !== undefined) {
+      joinPubByCode(websocket, code);
+    }
+  }, [code]);
+  return (
+    <div>
+      <h1>Joining pub...</h1>
+      <a href="#/Home">Back to the lobby</a>
+    </div>
+  );
+}
@@ -0,0 +1,63 @@
+import { useEffect } from "react";
+import { listNotifications, markRead } from "./commands";
+import { Notification } from "./Data";
+import { useUIStore } from "./Store";
+import { useWebsocket } from "./Websocket";
+
+function describe(notification: Notification, actor: string): string {
+  switch (notification.kind) {
+    case "Mention":
+      return `${actor} mentioned you: ${notification.excerpt}`;
+    case "TableInvite":
+      return `${actor} invited you to a table`;
+    case "Seated":
+      return notification.actor_id === null
+        ? "A seat came free at the table you were queueing for"
+        : `${actor} let you in to their table`;
+    case "Moderation":
+      return `${actor} did ${notification.action} to you${
+        notification.reason !== null ? `: ${notification.reason}` : ""
+      }`;
+  }
+}
+
+export function Notifications() {
+  const notifications = useUIStore((s) => s.notifications);
+  const unread = useUIStore((s) => s.unreadNotifications);
+  const persons = useUIStore((s) => s.persons);
+  const websocket = useWebsocket();
+  useEffect(() => {
+    listNotifications(websocket);
+  }, []);
+  return (
+    <div className="notifications">
+      <h4>
+        Notifications{unread > 0 && ` (${unread} unread)`}{" "}
+        {unread > 0 && notifications.length > 0 && (
+          <button
+            className="btn btn-sm btn-link"
+            onClick={() => markRead(websocket, notifications[0].id)}
+          >
+            Mark all read
+          </button>
+        )}
+      </h4>
+      <ul className="list-unstyled">
+        {notifications.map((n) => (
+          <li
+            key={n.id}
+            title={n.created}
+            className={n.read === null ? "font-weight-bold" : "text-muted"}
+          >
+            {describe(
+              n,
+              n.actor_id === null
+                ? "Someone"
+                : persons[n.actor_id]?.name ?? "Someone"
+            )}
+          </li>
+        ))}
+      </ul>
+    </div>
+  );
+}
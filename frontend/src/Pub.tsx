@@ -1,20 +1,206 @@
-import React from "react";
+import React, { useEffect } from "react";
 import { useState } from "react";
+import { Chat, ChatSearch } from "./Chat";
 import {
+  createPubInvite,
   createTable,
   deleteTable,
   joinTable,
+  knockTable,
   leavePub,
+  leaveQueue,
+  listPubInvites,
+  listRoles,
   listTables,
+  queueForTable,
+  revokePubInvite,
+  updatePub,
+  claimPub,
 } from "./commands";
+import { Pub as PubData, PubRole, Table, TableAccess } from "./Data";
 import { useUIStore } from "./Store";
 import { useWebsocket } from "./Websocket";
 
+// Asks for the password if the table needs one. Returns undefined if the
+// person gave up.
+function tablePassword(
+  table: Table,
+  invited: boolean
+): string | null | undefined {
+  if (table.access != "Password" || invited) {
+    return null;
+  }
+  return window.prompt(`Password for ${table.name}`) ?? undefined;
+}
+
+function inviteLink(token: string): string {
+  return `${window.location.origin}${window.location.pathname}#/Invite/${token}`;
+}
+
+function PubDetails(props: { pub: PubData; myRole: PubRole }) {
+  const { pub, myRole } = props;
+  const [editing, setEditing] = useState(false);
+  const [name, setName] = useState(pub.name);
+  const [description, setDescription] = useState(pub.description ?? "");
+  const [tags, setTags] = useState(pub.tags.join(", "));
+  const accountId = useUIStore((s) => s.accountId);
+  const websocket = useWebsocket();
+  if (!editing) {
+    return (
+      <div>
+        {pub.description !== null && <p>{pub.description}</p>}
+        {pub.tags.length > 0 && <div>Tags: {pub.tags.join(", ")}</div>}
+        {pub.owner_id === null && accountId !== null && (
+          <button
+            className="btn btn-secondary"
+            onClick={(evt) => {
+              claimPub(websocket, pub.id);
+              evt.preventDefault();
+            }}
+          >
+            Claim pub
+          </button>
+        )}
+        {myRole == "Landlord" && (
+          <button
+            className="btn btn-secondary"
+            onClick={(evt) => {
+              setName(pub.name);
+              setDescription(pub.description ?? "");
+              setTags(pub.tags.join(", "));
+              setEditing(true);
+              evt.preventDefault();
+            }}
+          >
+            Edit pub
+          </button>
+        )}
+      </div>
+    );
+  }
+  return (
+    <form>
+      <input
+        type="text"
+        className="form-control"
+        id="editPubName"
+        value={name}
+        onChange={(evt) => setName(evt.target.value)}
+      />
+      <textarea
+        className="form-control"
+        id="editPubDescription"
+        placeholder="What's this pub about?"
+        value={description}
+        onChange={(evt) => setDescription(evt.target.value)}
+      />
+      <input
+        type="text"
+        className="form-control"
+        id="editPubTags"
+        placeholder="Tags, separated by commas"
+        value={tags}
+        onChange={(evt) => setTags(evt.target.value)}
+      />
+      <button
+        type="button"
+        className="btn btn-primary"
+        onClick={(evt) => {
+          updatePub(websocket, pub.id, name, description, tags.split(","));
+          setEditing(false);
+          evt.preventDefault();
+        }}
+      >
+        Save
+      </button>
+      <button
+        type="button"
+        className="btn btn-secondary"
+        onClick={(evt) => {
+          setEditing(false);
+          evt.preventDefault();
+        }}
+      >
+        Cancel
+      </button>
+    </form>
+  );
+}
+
+function PubInvites(props: { pub: PubData; myRole: PubRole }) {
+  const { pub, myRole } = props;
+  const pubInvites = useUIStore((s) => s.pubInvites);
+  const inviteToken = useUIStore((s) => s.inviteToken);
+  const websocket = useWebsocket();
+  useEffect(() => {
+    if (myRole == "Landlord") {
+      listPubInvites(websocket, pub.id);
+    }
+  }, [pub.id, myRole]);
+  if (myRole == "Patron") {
+    return <React.Fragment />;
+  }
+  return (
+    <div>
+      <button
+        className="btn btn-secondary"
+        onClick={(evt) => {
+          createPubInvite(websocket, pub.id);
+          evt.preventDefault();
+        }}
+      >
+        Make invite link
+      </button>
+      {inviteToken !== null && inviteToken.pub_id == pub.id && (
+        <input
+          type="text"
+          className="form-control"
+          readOnly
+          value={inviteLink(inviteToken.token)}
+        />
+      )}
+      {myRole == "Landlord" && pubInvites.length > 0 && (
+        <ul>
+          {pubInvites
+            .filter((invite) => invite.pub_id == pub.id)
+            .map((invite) => (
+              <li key={invite.id} className="inviteItem">
+                Invite expiring {invite.expires}
+                <span>&nbsp;</span>
+                <button
+                  className="btn btn-danger"
+                  onClick={(evt) => {
+                    revokePubInvite(websocket, pub.id, invite.id);
+                    evt.preventDefault();
+                  }}
+                >
+                  Revoke
+                </button>
+              </li>
+            ))}
+        </ul>
+      )}
+    </div>
+  );
+}
+
 export function Pub() {
   const [tableName, setTableName] = useState("");
+  const [maxSeats, setMaxSeats] = useState("");
+  const tableError = useUIStore((s) => s.tableError);
+  const queue = useUIStore((s) => s.queue);
+  const invites = useUIStore((s) => s.invites);
+  const [access, setAccess] = useState<TableAccess>("Open");
+  const [password, setPassword] = useState("");
   const currentPub = useUIStore((s) => s.currentPub());
   const tables = useUIStore((s) => s.tables);
+  const myRole = useUIStore((s) => s.myRole());
   const websocket = useWebsocket();
+  useEffect(() => {
+    if (currentPub !== null) {
+      listRoles(websocket, currentPub.id);
+    }
+  }, [currentPub?.id]);
   if (currentPub === null) {
     // We'll nav away from here soon...
     return <React.Fragment />;
@@ -23,6 +209,11 @@ export function Pub() {
     <div>
       {" "}
       <h1 id="currentPubName">{currentPub.name}</h1>
+      <div id="currentPubCode">
+        Code: {currentPub.code}
+        {currentPub.slug !== null && ` (/p/${currentPub.slug})`}
+      </div>
+      <PubDetails pub={currentPub} myRole={myRole} />
       <br />
       <button
         className="btn btn-danger"
@@ -34,6 +225,7 @@ export function Pub() {
         Leave pub
       </button>
       <br />
+      <PubInvites pub={currentPub} myRole={myRole} />
       <input
         type="button"
         className="btn btn-secondary"
@@ -48,18 +240,73 @@ export function Pub() {
         {tables.map((table) => (
           <li key={table.id} className="tableItem">
             {table.name}
+            {table.topic !== null && `: ${table.topic}`}
+            {table.max_seats !== null &&
+              ` (${table.persons.length}/${table.max_seats} seats)`}
+            {table.access != "Open" && ` [${table.access}]`}
             <span>&nbsp;</span>
-            <button
-              className="btn btn-primary"
-              onClick={(evt) => {
-                joinTable(websocket, table.id);
-                evt.preventDefault();
-              }}
-            >
-              Join
-            </button>
+            {table.access == "Locked" && !invites.includes(table.id) ? (
+              <button className="btn btn-secondary" disabled>
+                Invite only
+              </button>
+            ) : table.access == "Knock" && !invites.includes(table.id) ? (
+              <button
+                className="btn btn-secondary"
+                onClick={(evt) => {
+                  knockTable(websocket, table.id);
+                  evt.preventDefault();
+                }}
+              >
+                Knock
+              </button>
+            ) : table.max_seats !== null &&
+              table.persons.length >= table.max_seats ? (
+              queue?.table_id == table.id ? (
+                <button
+                  className="btn btn-secondary"
+                  onClick={(evt) => {
+                    leaveQueue(websocket);
+                    evt.preventDefault();
+                  }}
+                >
+                  Leave queue ({queue.position} of {queue.length})
+                </button>
+              ) : (
+                <button
+                  className="btn btn-secondary"
+                  onClick={(evt) => {
+                    const password = tablePassword(
+                      table,
+                      invites.includes(table.id)
+                    );
+                    if (password !== undefined) {
+                      queueForTable(websocket, table.id, password);
+                    }
+                    evt.preventDefault();
+                  }}
+                >
+                  Queue
+                </button>
+              )
+            ) : (
+              <button
+                className="btn btn-primary"
+                onClick={(evt) => {
+                  const password = tablePassword(
+                    table,
+                    invites.includes(table.id)
+                  );
+                  if (password !== undefined) {
+                    joinTable(websocket, table.id, password);
+                  }
+                  evt.preventDefault();
+                }}
+              >
+                Join
+              </button>
+            )}
             <span>&nbsp;</span>
-            {table.persons.length == 0 && (
+            {table.persons.length == 0 && myRole != "Patron" && (
               <button
                 className="btn btn-danger"
                 onClick={(evt) => {
@@ -73,6 +320,11 @@ export function Pub() {
           </li>
         ))}
       </ul>
+      {tableError !== null && (
+        <div id="tableError" className="text-danger">
+          {tableError}
+        </div>
+      )}
       <form>
         <div className="form-group">
           <label htmlFor="tableName">New table</label>
@@ -87,19 +339,67 @@ export function Pub() {
               evt.preventDefault();
             }}
           />
+          <input
+            type="number"
+            min="1"
+            className="form-control"
+            id="maxSeats"
+            placeholder="Seats (leave empty for no limit)"
+            value={maxSeats}
+            onChange={(evt) => {
+              setMaxSeats(evt.target.value);
+              evt.preventDefault();
+            }}
+          />
+          <select
+            className="form-control"
+            id="tableAccess"
+            value={access}
+            onChange={(evt) => {
+              setAccess(evt.target.value as TableAccess);
+              evt.preventDefault();
+            }}
+          >
+            <option value="Open">Anyone can join</option>
+            <option value="Locked">Invite only</option>
+            <option value="Password">Password protected</option>
+            <option value="Knock">Knock to enter</option>
+          </select>
+          {access == "Password" && (
+            <input
+              type="password"
+              className="form-control"
+              id="tablePassword"
+              placeholder="Table password"
+              value={password}
+              onChange={(evt) => {
+                setPassword(evt.target.value);
+                evt.preventDefault();
+              }}
+            />
+          )}
         </div>
         <button
           id="createTable"
           type="button"
           className="btn btn-primary"
           onClick={(evt) => {
-            createTable(websocket, currentPub.id, tableName);
+            createTable(
+              websocket,
+              currentPub.id,
+              tableName,
+              maxSeats === "" ? null : parseInt(maxSeats),
+              access,
+              access == "Password" ? password : null
+            );
             evt.preventDefault();
           }}
         >
           Create table
         </button>
       </form>
+      <Chat scope={{ kind: "Pub", pub_id: currentPub.id }} />
+      <ChatSearch pubId={currentPub.id} />
     </div>
   );
 }
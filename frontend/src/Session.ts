@@ -0,0 +1,155 @@
+import { useUIStore } from "./Store";
+
+interface SessionToken {
+  token: string;
+  person_id: string;
+  account_id: string | null;
+  expires: number;
+}
+
+// Refresh tokens when there's less than a day left on them
+const REFRESH_WINDOW_SECONDS = 24 * 60 * 60;
+
+let pending: Promise<void> | null = null;
+
+async function requestSession(token: string | null): Promise<SessionToken> {
+  const res = await fetch("/api/session", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ token }),
+  });
+  if (res.status === 401 && token !== null) {
+    console.info("Session token rejected, starting a new guest session");
+    return requestSession(null);
+  }
+  if (!res.ok) {
+    throw new Error(`Failed to get a session: ${res.status}`);
+  }
+  return res.json();
+}
+
+function storeSession(session: SessionToken) {
+  useUIStore.setState((s) => ({
+    ...s,
+    token: session.token,
+    tokenExpires: session.expires,
+    peerId: session.person_id,
+    accountId: session.account_id,
+  }));
+}
+
+async function accountRequest(
+  path: string,
+  body: object
+): Promise<string | null> {
+  const res = await fetch(path, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(body),
+  });
+  if (!res.ok) {
+    const error = await res.json();
+    return error.message;
+  }
+  storeSession(await res.json());
+  return null;
+}
+
+// These return an error message on failure
+export function register(username: string, password: string) {
+  return accountRequest("/api/register", { username, password });
+}
+
+export function login(username: string, password: string) {
+  return accountRequest("/api/login", { username, password });
+}
+
+export async function logout() {
+  const token = useUIStore.getState().token;
+  if (token !== null) {
+    await fetch("/api/logout", {
+      method: "POST",
+      headers: { Authorization: `Bearer ${token}` },
+    });
+  }
+  useUIStore.setState((s) => ({
+    ...s,
+    token: null,
+    tokenExpires: null,
+    accountId: null,
+  }));
+  ensureSession();
+}
+
+// After logging in with an identity provider, the backend sends us back with
+// a one-time code to swap for a session
+function takeLoginCodeFromUrl(): string | null {
+  const url = new URL(window.location.href);
+  const code = url.searchParams.get("login");
+  if (code === null) {
+    return null;
+  }
+  url.searchParams.delete("login");
+  window.history.replaceState(null, "", url.toString());
+  return code;
+}
+
+async function swapLoginCode(code: string): Promise<SessionToken> {
+  const res = await fetch("/api/oidc/session", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ code }),
+  });
+  if (!res.ok) {
+    throw new Error(`Failed to finish logging in: ${res.status}`);
+  }
+  return res.json();
+}
+
+// Links an identity provider login to the logged in account. Returns an error
+// message on failure, otherwise the browser goes off to the identity provider.
+export async function linkSsoLogin(): Promise<string | null> {
+  const token = useUIStore.getState().token;
+  const res = await fetch("/api/oidc/link", {
+    method: "POST",
+    headers: { Authorization: `Bearer ${token}` },
+  });
+  if (!res.ok) {
+    const error = await res.json();
+    return error.message;
+  }
+  const { url } = await res.json();
+  window.location.assign(url);
+  return null;
+}
+
+export function ensureSession() {
+  const code = takeLoginCodeFromUrl();
+  if (code !== null && pending === null) {
+    pending = swapLoginCode(code)
+      .then(storeSession)
+      .catch((err) => console.warn("login error", err))
+      .finally(() => {
+        pending = null;
+      });
+    return;
+  }
+  const { token, tokenExpires } = useUIStore.getState();
+  const now = Date.now() / 1000;
+  if (
+    token !== null &&
+    tokenExpires !== null &&
+    tokenExpires - now > REFRESH_WINDOW_SECONDS
+  ) {
+    return;
+  }
+  if (pending !== null) {
+    return;
+  }
+  pending = requestSession(token)
+    .then(storeSession)
+    .catch((err) => console.warn("session error", err))
+    .finally(() => {
+      pending = null;
+    });
+}
@@ -1,10 +1,24 @@
 import create from "zustand";
 import { devtools, persist } from "zustand/middleware";
-import { v4 as uuidv4 } from "uuid";
-import { Person, Pub, Table } from "./Data";
+import {
+  Block,
+  ChatMessage,
+  ChatSearchHit,
+  Conversation,
+  DirectMessage,
+  Notification,
+  Person,
+  Pub,
+  PubInvite,
+  PubRole,
+  Table,
+} from "./Data";
 
 interface IUIStore {
   peerId: string;
+  token: string | null;
+  tokenExpires: number | null;
+  accountId: string | null;
   peers: { [key: string]: RTCPeerConnection };
   pubs: Pub[];
   tables: Table[];
@@ -13,6 +27,39 @@ interface IUIStore {
   currentTable: () => Table | null;
   mediaStream: MediaProvider | null;
   persons: { [key: string]: Person };
+  // Roles in the current pub, for everyone who isn't a patron
+  roles: { [key: string]: PubRole };
+  myRole: () => PubRole;
+  // Why we couldn't sit at a table, e.g. because it's full
+  tableError: string | null;
+  queue: { table_id: string; position: number; length: number } | null;
+  // Tables we've been invited to
+  invites: string[];
+  // People asking to be let into our table
+  knocks: { table_id: string; person_id: string }[];
+  // Outstanding invites to the current pub, for its landlord
+  pubInvites: PubInvite[];
+  // The last invite link we made
+  inviteToken: { pub_id: string; token: string } | null;
+  // Chat for the current pub and its tables, oldest first
+  chat: ChatMessage[];
+  // The last chat search, best matches first
+  searchResults: {
+    pub_id: string;
+    query: string;
+    list: ChatSearchHit[];
+  } | null;
+  // Most recent conversation first
+  inbox: Conversation[];
+  // Direct messages we've seen, with anyone, oldest first
+  directMessages: DirectMessage[];
+  // Who we've got a conversation open with
+  conversationWith: string | null;
+  // Newest first
+  notifications: Notification[];
+  unreadNotifications: number;
+  // People we've blocked
+  blocks: Block[];
 }
 
 export const useUIStore = create<IUIStore>()(
@@ -20,11 +67,38 @@ export const useUIStore = create<IUIStore>()(
     devtools(
       (set, get) =>
         ({
-          peerId: uuidv4(),
+          peerId: "",
+          token: null,
+          tokenExpires: null,
+          accountId: null,
           peers: {},
           pubs: [],
           persons: {},
           tables: [],
+          roles: {},
+          tableError: null,
+          queue: null,
+          invites: [],
+          knocks: [],
+          pubInvites: [],
+          inviteToken: null,
+          chat: [],
+          searchResults: null,
+          inbox: [],
+          directMessages: [],
+          conversationWith: null,
+          notifications: [],
+          unreadNotifications: 0,
+          blocks: [],
+          myRole: () => {
+            // Pubs from before owners were tracked have no landlord at all
+            const peerId = get().peerId;
+            const pub = get().currentPub();
+            if (pub !== null && pub.owner_id == peerId) {
+              return "Landlord";
+            }
+            return get().roles[peerId] ?? "Patron";
+          },
           me: () => {
             {
               if (get().peerId in get().persons) {
@@ -66,6 +140,12 @@ export const useUIStore = create<IUIStore>()(
       partialize: (state) => ({
         ...state,
         mediaStream: null,
+        chat: [],
+        searchResults: null,
+        inbox: [],
+        directMessages: [],
+        notifications: [],
+        unreadNotifications: 0,
       }),
     }
   )
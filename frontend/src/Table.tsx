@@ -1,5 +1,12 @@
-import React from "react";
-import { leaveTable } from "./commands";
+import React, { useEffect, useState } from "react";
+import { Chat } from "./Chat";
+import {
+  admit,
+  claimMedia,
+  deny,
+  leaveTable,
+  updateTable,
+} from "./commands";
 import { useUIStore } from "./Store";
 import { Videos } from "./Video";
 import { useWebsocket } from "./Websocket";
@@ -7,7 +14,13 @@ import { useWebsocket } from "./Websocket";
 export function Table() {
   const currentPub = useUIStore((s) => s.currentPub());
   const currentTable = useUIStore((s) => s.currentTable());
+  const knocks = useUIStore((s) => s.knocks);
+  const persons = useUIStore((s) => s.persons);
+  const [topic, setTopic] = useState("");
   const websocket = useWebsocket();
+  useEffect(() => {
+    claimMedia(websocket);
+  }, [currentTable?.id]);
   if (currentPub === null || currentTable == null) {
     // We'll nav away from here soon...
     return <React.Fragment />;
@@ -17,6 +30,28 @@ export function Table() {
       <h1>
         {currentPub.name}: {currentTable.name}
       </h1>
+      {currentTable.topic !== null && <h4>{currentTable.topic}</h4>}
+      <form className="form-inline">
+        <input
+          type="text"
+          className="form-control"
+          id="tableTopic"
+          placeholder="Change the topic"
+          value={topic}
+          onChange={(evt) => setTopic(evt.target.value)}
+        />
+        <button
+          type="button"
+          className="btn btn-secondary"
+          onClick={(evt) => {
+            updateTable(websocket, currentTable.id, null, topic);
+            setTopic("");
+            evt.preventDefault();
+          }}
+        >
+          Set topic
+        </button>
+      </form>
       <br />
       <button
         className="btn btn-danger"
@@ -27,7 +62,35 @@ export function Table() {
       >
         Leave table
       </button>
+      {knocks
+        .filter((knock) => knock.table_id == currentTable.id)
+        .map((knock) => (
+          <div key={knock.person_id} className="knock">
+            {persons[knock.person_id]?.name ?? "Someone"} is knocking
+            <span>&nbsp;</span>
+            <button
+              className="btn btn-primary"
+              onClick={(evt) => {
+                admit(websocket, currentTable.id, knock.person_id);
+                evt.preventDefault();
+              }}
+            >
+              Let in
+            </button>
+            <span>&nbsp;</span>
+            <button
+              className="btn btn-secondary"
+              onClick={(evt) => {
+                deny(websocket, currentTable.id, knock.person_id);
+                evt.preventDefault();
+              }}
+            >
+              Turn away
+            </button>
+          </div>
+        ))}
       <Videos />
+      <Chat scope={{ kind: "Table", table_id: currentTable.id }} />
     </div>
   );
 }
@@ -1,5 +1,5 @@
 import React, { useEffect, useState } from "react";
-import { send } from "./commands";
+import { hangup, iceCandidate, offer } from "./commands";
 import { useUIStore } from "./Store";
 import { useWebsocket } from "./Websocket";
 
@@ -64,15 +64,17 @@ function VideoComponent({
           conn.onicecandidate = (candidate) => {
             console.log("candidate", candidate);
             if (candidate.candidate !== null) {
-              send(websocket, name, JSON.stringify(candidate.candidate));
+              iceCandidate(websocket, name, candidate.candidate.toJSON());
             }
           };
           conn.onnegotiationneeded = () => {
-            conn.createOffer().then((offer) => {
-              console.log("offer", offer);
-              conn.setLocalDescription(offer).then(() => {
+            conn.createOffer().then((desc) => {
+              console.log("offer", desc);
+              conn.setLocalDescription(desc).then(() => {
                 console.log("local desc", conn.localDescription);
-                send(websocket, name, JSON.stringify(conn.localDescription));
+                if (conn.localDescription !== null) {
+                  offer(websocket, name, conn.localDescription.sdp);
+                }
               });
             });
           };
@@ -98,6 +100,7 @@ function VideoComponent({
     return () => {
       console.log("unmounting", name);
       if (rtcpeer != null) {
+        hangup(websocket, name);
         rtcpeer.close();
         setRtcpeer(null);
       }
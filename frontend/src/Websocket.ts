@@ -1,19 +1,24 @@
 import { useUIStore } from "./Store";
-import { listPubs, listTables } from "./commands";
+import { listTables, subscribeLobby } from "./commands";
 import { WebsocketWrapper } from "./WebsocketHelper";
 import { doMessage, SocketMessage } from "./messages";
+import { ensureSession } from "./Session";
 
 export const useWebsocket = () => {
-  const peerId = useUIStore((state) => state.peerId);
-  const path = `wss://${window.location.hostname}:${window.location.port}/ws/${peerId}`;
-  console.log(`ws path: ${path}`);
+  const token = useUIStore((state) => state.token);
   const currentPubId = useUIStore((s) => {
     const me = s.me();
     return me && me.pub_id;
   });
+  ensureSession();
+  if (token === null) {
+    // Messages will be buffered until we've got a session to connect with
+    return WebsocketWrapper;
+  }
+  const path = `wss://${window.location.hostname}:${window.location.port}/ws`;
   WebsocketWrapper.setOpenFunc(() => {
     console.debug("Websocket connected");
-    listPubs(WebsocketWrapper);
+    subscribeLobby(WebsocketWrapper);
     if (currentPubId !== null) {
       listTables(WebsocketWrapper, currentPubId);
     }
@@ -23,6 +28,8 @@ export const useWebsocket = () => {
     const decoded: SocketMessage = JSON.parse(message.data as string);
     doMessage(WebsocketWrapper, decoded);
   });
-  WebsocketWrapper.connect(path);
+  // The session token goes in as a subprotocol, so it stays out of URLs and
+  // the logs they end up in
+  WebsocketWrapper.connect(path, ["tavern", token]);
   return WebsocketWrapper;
 };
@@ -1,5 +1,6 @@
 let websocket: WebSocket | null = null;
 let current_url: string | null = null;
+let current_protocols: string[] = [];
 let openfunc: (() => void) | null = null;
 let messagefunc: ((message: MessageEvent) => void) | null = null;
 const messageQueue: string[] = [];
@@ -23,14 +24,20 @@ export class websocketWrapper {
     }
   }
 
-  connect(url: string | null) {
+  connect(url: string | null, protocols: string[] = []) {
     if (url !== null) {
-      if (websocket !== null && current_url !== url) {
-        console.info(`URL change from ${current_url} to ${url}, swapping`);
+      if (
+        websocket !== null &&
+        (current_url !== url ||
+          current_protocols.join(",") !== protocols.join(","))
+      ) {
+        // Protocols can carry the session token, so they're not logged
+        console.info(`Connection change for ${url}, swapping`);
         websocket.close();
         websocket = null;
       }
       current_url = url;
+      current_protocols = protocols;
     } else {
       if (current_url === null) {
         console.warn("Can't connect as current_url is null");
@@ -39,7 +46,7 @@ export class websocketWrapper {
     }
     if (websocket === null || websocket.readyState === WebSocket.CLOSED) {
       console.info(`Connecting: ${current_url}`);
-      websocket = new WebSocket(current_url);
+      websocket = new WebSocket(current_url, current_protocols);
       websocket.onerror = (error) => {
         console.debug("Websocket error", JSON.stringify(error));
       };
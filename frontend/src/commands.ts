@@ -1,9 +1,18 @@
+import { ChatScope, PubRole, PubVisibility, TableAccess } from "./Data";
 import { websocketWrapper } from "./WebsocketHelper";
 
 interface ListPubsCommand {
   kind: "ListPubs";
 }
 
+interface SubscribeLobbyCommand {
+  kind: "SubscribeLobby";
+}
+
+interface UnsubscribeLobbyCommand {
+  kind: "UnsubscribeLobby";
+}
+
 interface DeletePubCommand {
   kind: "DeletePub";
   pub_id: string;
@@ -14,9 +23,52 @@ interface JoinPubCommand {
   pub_id: string;
 }
 
+interface JoinPubByInviteCommand {
+  kind: "JoinPubByInvite";
+  token: string;
+}
+
+interface UpdatePubCommand {
+  kind: "UpdatePub";
+  pub_id: string;
+  name: string | null;
+  description: string | null;
+  tags: string[] | null;
+}
+
+interface ClaimPubCommand {
+  kind: "ClaimPub";
+  pub_id: string;
+}
+
+interface JoinPubByCodeCommand {
+  kind: "JoinPubByCode";
+  code: string;
+}
+
 interface CreatePubCommand {
   kind: "CreatePub";
   name: string;
+  visibility: PubVisibility;
+  slug: string | null;
+}
+
+interface CreatePubInviteCommand {
+  kind: "CreatePubInvite";
+  pub_id: string;
+  ttl: number | null;
+}
+
+interface ListPubInvitesCommand {
+  kind: "ListPubInvites";
+  pub_id: string;
+}
+
+interface RevokePubInviteCommand {
+  kind: "RevokePubInvite";
+  pub_id: string;
+  invite_id: string;
+  reason: string | null;
 }
 
 interface LeavePubCommand {
@@ -32,10 +84,14 @@ interface CreateTableCommand {
   kind: "CreateTable";
   pub_id: string;
   name: string;
+  max_seats: number | null;
+  access: TableAccess;
+  password: string | null;
 }
 interface JoinTableCommand {
   kind: "JoinTable";
   table_id: string;
+  password: string | null;
 }
 interface LeaveTableCommand {
   kind: "LeaveTable";
@@ -45,6 +101,12 @@ interface DeleteTableCommand {
   kind: "DeleteTable";
   table_id: string;
 }
+interface UpdateTableCommand {
+  kind: "UpdateTable";
+  table_id: string;
+  name: string | null;
+  topic: string | null;
+}
 interface GetPersonCommand {
   kind: "GetPerson";
   user_id: string;
@@ -54,23 +116,235 @@ interface SendCommand {
   user_id: string;
   content: string;
 }
+interface OfferCommand {
+  kind: "Offer";
+  user_id: string;
+  sdp: string;
+}
+interface AnswerCommand {
+  kind: "Answer";
+  user_id: string;
+  sdp: string;
+}
+interface IceCandidateCommand {
+  kind: "IceCandidate";
+  user_id: string;
+  candidate: RTCIceCandidateInit;
+}
+interface RenegotiateCommand {
+  kind: "Renegotiate";
+  user_id: string;
+}
+interface HangupCommand {
+  kind: "Hangup";
+  user_id: string;
+}
+interface ClaimMediaCommand {
+  kind: "ClaimMedia";
+}
+interface ListRolesCommand {
+  kind: "ListRoles";
+  pub_id: string;
+}
+interface SetRoleCommand {
+  kind: "SetRole";
+  pub_id: string;
+  user_id: string;
+  role: PubRole;
+}
+interface KickCommand {
+  kind: "Kick";
+  user_id: string;
+}
+interface BanCommand {
+  kind: "Ban";
+  user_id: string;
+  duration: number | null;
+}
+interface UnbanCommand {
+  kind: "Unban";
+  user_id: string;
+}
+interface MuteCommand {
+  kind: "Mute";
+  user_id: string;
+}
+interface UnmuteCommand {
+  kind: "Unmute";
+  user_id: string;
+}
+interface ListAuditLogCommand {
+  kind: "ListAuditLog";
+  pub_id: string;
+  before: number | null;
+  limit: number | null;
+}
+interface QueueForTableCommand {
+  kind: "QueueForTable";
+  table_id: string;
+  password: string | null;
+}
+interface InviteToTableCommand {
+  kind: "InviteToTable";
+  table_id: string;
+  user_id: string;
+}
+interface KnockTableCommand {
+  kind: "KnockTable";
+  table_id: string;
+}
+interface AdmitCommand {
+  kind: "Admit";
+  table_id: string;
+  user_id: string;
+}
+interface DenyCommand {
+  kind: "Deny";
+  table_id: string;
+  user_id: string;
+}
+interface LeaveQueueCommand {
+  kind: "LeaveQueue";
+}
+interface ChatMessageCommand {
+  kind: "ChatMessage";
+  scope: ChatScope;
+  content: string;
+}
+interface ChatHistoryCommand {
+  kind: "ChatHistory";
+  scope: ChatScope;
+  before: number | null;
+  limit: number | null;
+}
+interface EditMessageCommand {
+  kind: "EditMessage";
+  message_id: number;
+  content: string;
+}
+interface DeleteMessageCommand {
+  kind: "DeleteMessage";
+  message_id: number;
+  reason: string | null;
+}
+interface ReactCommand {
+  kind: "React";
+  message_id: number;
+  emoji: string;
+}
+interface ListMessageVersionsCommand {
+  kind: "ListMessageVersions";
+  message_id: number;
+}
+interface SearchMessagesCommand {
+  kind: "SearchMessages";
+  pub_id: string;
+  query: string;
+  limit: number | null;
+}
+interface DirectMessageCommand {
+  kind: "DirectMessage";
+  user_id: string;
+  content: string;
+}
+interface InboxCommand {
+  kind: "Inbox";
+}
+interface DirectMessageHistoryCommand {
+  kind: "DirectMessageHistory";
+  user_id: string;
+  before: number | null;
+  limit: number | null;
+}
+interface MarkDirectMessagesReadCommand {
+  kind: "MarkDirectMessagesRead";
+  user_id: string;
+  up_to: number;
+}
+interface ListNotificationsCommand {
+  kind: "ListNotifications";
+  before: number | null;
+  limit: number | null;
+}
+interface MarkReadCommand {
+  kind: "MarkRead";
+  up_to: number;
+}
+interface BlockCommand {
+  kind: "Block";
+  user_id: string;
+  keep_off_tables: boolean | null;
+}
+interface UnblockCommand {
+  kind: "Unblock";
+  user_id: string;
+}
+interface ListBlocksCommand {
+  kind: "ListBlocks";
+}
 interface PingCommand {
   kind: "Ping";
 }
 
 type Command =
   | ListPubsCommand
+  | SubscribeLobbyCommand
+  | UnsubscribeLobbyCommand
   | DeletePubCommand
+  | UpdatePubCommand
+  | ClaimPubCommand
   | JoinPubCommand
+  | JoinPubByInviteCommand
+  | JoinPubByCodeCommand
   | CreatePubCommand
+  | CreatePubInviteCommand
+  | ListPubInvitesCommand
+  | RevokePubInviteCommand
   | LeavePubCommand
   | ListTablesCommand
   | CreateTableCommand
   | JoinTableCommand
   | LeaveTableCommand
   | DeleteTableCommand
+  | UpdateTableCommand
   | GetPersonCommand
   | SendCommand
+  | OfferCommand
+  | AnswerCommand
+  | IceCandidateCommand
+  | RenegotiateCommand
+  | HangupCommand
+  | ClaimMediaCommand
+  | ListRolesCommand
+  | SetRoleCommand
+  | KickCommand
+  | BanCommand
+  | UnbanCommand
+  | MuteCommand
+  | UnmuteCommand
+  | ListAuditLogCommand
+  | QueueForTableCommand
+  | LeaveQueueCommand
+  | InviteToTableCommand
+  | KnockTableCommand
+  | AdmitCommand
+  | DenyCommand
+  | ChatMessageCommand
+  | ChatHistoryCommand
+  | EditMessageCommand
+  | DeleteMessageCommand
+  | ReactCommand
+  | ListMessageVersionsCommand
+  | SearchMessagesCommand
+  | DirectMessageCommand
+  | InboxCommand
+  | DirectMessageHistoryCommand
+  | MarkDirectMessagesReadCommand
+  | ListNotificationsCommand
+  | MarkReadCommand
+  | BlockCommand
+  | UnblockCommand
+  | ListBlocksCommand
   | PingCommand;
 
 export type WS = websocketWrapper;
@@ -85,18 +359,106 @@ export function listPubs(websocket: WS): void {
   sendCommand(websocket, { kind: "ListPubs" });
 }
 
-export function createPub(websocket: WS, name: string) {
-  sendCommand(websocket, { kind: "CreatePub", name: name });
+export function subscribeLobby(websocket: WS): void {
+  sendCommand(websocket, { kind: "SubscribeLobby" });
+}
+
+export function unsubscribeLobby(websocket: WS): void {
+  sendCommand(websocket, { kind: "UnsubscribeLobby" });
+}
+
+export function createPub(
+  websocket: WS,
+  name: string,
+  visibility: PubVisibility = "Public",
+  slug: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "CreatePub",
+    name: name,
+    visibility: visibility,
+    slug: slug,
+  });
 }
 
 export function deletePub(websocket: WS, pubId: string) {
   sendCommand(websocket, { kind: "DeletePub", pub_id: pubId });
 }
 
+// Anything left as null stays as it is
+export function updatePub(
+  websocket: WS,
+  pubId: string,
+  name: string | null,
+  description: string | null = null,
+  tags: string[] | null = null
+) {
+  sendCommand(websocket, {
+    kind: "UpdatePub",
+    pub_id: pubId,
+    name: name,
+    description: description,
+    tags: tags,
+  });
+}
+
+// Only works for pubs without an owner
+export function claimPub(websocket: WS, pubId: string) {
+  sendCommand(websocket, { kind: "ClaimPub", pub_id: pubId });
+}
+
+export function updateTable(
+  websocket: WS,
+  tableId: string,
+  name: string | null,
+  topic: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "UpdateTable",
+    table_id: tableId,
+    name: name,
+    topic: topic,
+  });
+}
+
 export function joinPub(websocket: WS, pubId: string) {
   sendCommand(websocket, { kind: "JoinPub", pub_id: pubId });
 }
 
+export function joinPubByInvite(websocket: WS, token: string) {
+  sendCommand(websocket, { kind: "JoinPubByInvite", token: token });
+}
+
+export function joinPubByCode(websocket: WS, code: string) {
+  sendCommand(websocket, { kind: "JoinPubByCode", code: code });
+}
+
+export function createPubInvite(
+  websocket: WS,
+  pubId: string,
+  ttl: number | null = null
+) {
+  sendCommand(websocket, { kind: "CreatePubInvite", pub_id: pubId, ttl: ttl });
+}
+
+export function listPubInvites(websocket: WS, pubId: string) {
+  sendCommand(websocket, { kind: "ListPubInvites", pub_id: pubId });
+}
+
+export function revokePubInvite(
+  websocket: WS,
+  pubId: string,
+  inviteId: string,
+  reason: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "RevokePubInvite",
+    pub_id: pubId,
+    invite_id: inviteId,
+    reason: reason,
+  });
+}
+
 export function leavePub(websocket: WS, pubId: string) {
   sendCommand(websocket, { kind: "LeavePub", pub_id: pubId });
 }
@@ -105,12 +467,30 @@ export function listTables(websocket: WS, pubId: string) {
   sendCommand(websocket, { kind: "ListTables", pub_id: pubId });
 }
 
-export function createTable(websocket: WS, pubId: string, name: string) {
-  sendCommand(websocket, { kind: "CreateTable", pub_id: pubId, name });
+export function createTable(
+  websocket: WS,
+  pubId: string,
+  name: string,
+  maxSeats: number | null = null,
+  access: TableAccess = "Open",
+  password: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "CreateTable",
+    pub_id: pubId,
+    name,
+    max_seats: maxSeats,
+    access,
+    password,
+  });
 }
 
-export function joinTable(websocket: WS, tableId: string) {
-  sendCommand(websocket, { kind: "JoinTable", table_id: tableId });
+export function joinTable(
+  websocket: WS,
+  tableId: string,
+  password: string | null = null
+) {
+  sendCommand(websocket, { kind: "JoinTable", table_id: tableId, password });
 }
 
 export function leaveTable(websocket: WS, tableId: string) {
@@ -129,6 +509,272 @@ export function send(websocket: WS, userId: string, content: string) {
   sendCommand(websocket, { kind: "Send", user_id: userId, content });
 }
 
+export function offer(websocket: WS, userId: string, sdp: string) {
+  sendCommand(websocket, { kind: "Offer", user_id: userId, sdp });
+}
+
+export function answer(websocket: WS, userId: string, sdp: string) {
+  sendCommand(websocket, { kind: "Answer", user_id: userId, sdp });
+}
+
+export function iceCandidate(
+  websocket: WS,
+  userId: string,
+  candidate: RTCIceCandidateInit
+) {
+  sendCommand(websocket, { kind: "IceCandidate", user_id: userId, candidate });
+}
+
+export function renegotiate(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Renegotiate", user_id: userId });
+}
+
+export function hangup(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Hangup", user_id: userId });
+}
+
+export function claimMedia(websocket: WS) {
+  sendCommand(websocket, { kind: "ClaimMedia" });
+}
+
+export function listRoles(websocket: WS, pubId: string) {
+  sendCommand(websocket, { kind: "ListRoles", pub_id: pubId });
+}
+
+export function setRole(
+  websocket: WS,
+  pubId: string,
+  userId: string,
+  role: PubRole
+) {
+  sendCommand(websocket, {
+    kind: "SetRole",
+    pub_id: pubId,
+    user_id: userId,
+    role,
+  });
+}
+
+export function kick(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Kick", user_id: userId });
+}
+
+// Duration is in seconds, and bans without one last until they're lifted
+export function ban(websocket: WS, userId: string, duration: number | null) {
+  sendCommand(websocket, { kind: "Ban", user_id: userId, duration });
+}
+
+export function unban(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Unban", user_id: userId });
+}
+
+export function mute(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Mute", user_id: userId });
+}
+
+export function unmute(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Unmute", user_id: userId });
+}
+
+export function queueForTable(
+  websocket: WS,
+  tableId: string,
+  password: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "QueueForTable",
+    table_id: tableId,
+    password,
+  });
+}
+
+export function inviteToTable(websocket: WS, tableId: string, userId: string) {
+  sendCommand(websocket, {
+    kind: "InviteToTable",
+    table_id: tableId,
+    user_id: userId,
+  });
+}
+
+export function knockTable(websocket: WS, tableId: string) {
+  sendCommand(websocket, { kind: "KnockTable", table_id: tableId });
+}
+
+export function admit(websocket: WS, tableId: string, userId: string) {
+  sendCommand(websocket, { kind: "Admit", table_id: tableId, user_id: userId });
+}
+
+export function deny(websocket: WS, tableId: string, userId: string) {
+  sendCommand(websocket, { kind: "Deny", table_id: tableId, user_id: userId });
+}
+
+export function leaveQueue(websocket: WS) {
+  sendCommand(websocket, { kind: "LeaveQueue" });
+}
+
+// Pass the id of the oldest event seen as `before` to get the page before it
+export function listAuditLog(
+  websocket: WS,
+  pubId: string,
+  before: number | null = null,
+  limit: number | null = null
+) {
+  sendCommand(websocket, {
+    kind: "ListAuditLog",
+    pub_id: pubId,
+    before,
+    limit,
+  });
+}
+
 export function ping(websocket: WS) {
   sendCommand(websocket, { kind: "Ping" });
 }
+
+export function chatMessage(websocket: WS, scope: ChatScope, content: string) {
+  sendCommand(websocket, {
+    kind: "ChatMessage",
+    scope: scope,
+    content: content,
+  });
+}
+
+export function chatHistory(
+  websocket: WS,
+  scope: ChatScope,
+  before: number | null = null,
+  limit: number | null = null
+) {
+  sendCommand(websocket, {
+    kind: "ChatHistory",
+    scope: scope,
+    before: before,
+    limit: limit,
+  });
+}
+
+export function editMessage(websocket: WS, messageId: number, content: string) {
+  sendCommand(websocket, {
+    kind: "EditMessage",
+    message_id: messageId,
+    content: content,
+  });
+}
+
+export function deleteMessage(
+  websocket: WS,
+  messageId: number,
+  reason: string | null = null
+) {
+  sendCommand(websocket, {
+    kind: "DeleteMessage",
+    message_id: messageId,
+    reason: reason,
+  });
+}
+
+// Reacting with the same emoji again takes it back
+export function react(websocket: WS, messageId: number, emoji: string) {
+  sendCommand(websocket, {
+    kind: "React",
+    message_id: messageId,
+    emoji: emoji,
+  });
+}
+
+export function listMessageVersions(websocket: WS, messageId: number) {
+  sendCommand(websocket, {
+    kind: "ListMessageVersions",
+    message_id: messageId,
+  });
+}
+
+export function searchMessages(
+  websocket: WS,
+  pubId: string,
+  query: string,
+  limit: number | null = null
+) {
+  sendCommand(websocket, {
+    kind: "SearchMessages",
+    pub_id: pubId,
+    query: query,
+    limit: limit,
+  });
+}
+
+export function directMessage(websocket: WS, userId: string, content: string) {
+  sendCommand(websocket, {
+    kind: "DirectMessage",
+    user_id: userId,
+    content: content,
+  });
+}
+
+export function inbox(websocket: WS) {
+  sendCommand(websocket, { kind: "Inbox" });
+}
+
+export function directMessageHistory(
+  websocket: WS,
+  userId: string,
+  before: number | null = null,
+  limit: number | null = null
+) {
+  sendCommand(websocket, {
+    kind: "DirectMessageHistory",
+    user_id: userId,
+    before: before,
+    limit: limit,
+  });
+}
+
+// Marks everything from them up to and including upTo as read
+export function markDirectMessagesRead(
+  websocket: WS,
+  userId: string,
+  upTo: number
+) {
+  sendCommand(websocket, {
+    kind: "MarkDirectMessagesRead",
+    user_id: userId,
+    up_to: upTo,
+  });
+}
+
+export function listNotifications(
+  websocket: WS,
+  before: number | null = null,
+  limit: number | null = null
+) {
+  sendCommand(websocket, {
+    kind: "ListNotifications",
+    before: before,
+    limit: limit,
+  });
+}
+
+// Marks all our notifications up to and including upTo as read
+export function markRead(websocket: WS, upTo: number) {
+  sendCommand(websocket, { kind: "MarkRead", up_to: upTo });
+}
+
+export function block(
+  websocket: WS,
+  userId: string,
+  keepOffTables: boolean | null = null
+) {
+  sendCommand(websocket, {
+    kind: "Block",
+    user_id: userId,
+    keep_off_tables: keepOffTables,
+  });
+}
+
+export function unblock(websocket: WS, userId: string) {
+  sendCommand(websocket, { kind: "Unblock", user_id: userId });
+}
+
+export function listBlocks(websocket: WS) {
+  sendCommand(websocket, { kind: "ListBlocks" });
+}
@@ -1,13 +1,47 @@
-import { Person, Pub, Table } from "./Data";
+import {
+  AuditEvent,
+  Block,
+  ChatMessage,
+  ChatMessageVersion,
+  ChatScope,
+  ChatSearchHit,
+  Conversation,
+  DirectMessage,
+  Notification,
+  Person,
+  Pub,
+  PubInvite,
+  PubRole,
+  PubRoleEntry,
+  Receipt,
+  Table,
+} from "./Data";
 import produce from "immer";
 import { useUIStore } from "./Store";
-import { send, WS } from "./commands";
+import { answer, WS } from "./commands";
 
 interface PubsMessage {
   kind: "Pubs";
   list: Pub[];
 }
 
+interface PubMessage {
+  kind: "Pub";
+  data: Pub;
+}
+
+interface PubInviteMessage {
+  kind: "PubInvite";
+  data: PubInvite;
+  token: string;
+}
+
+interface PubInvitesMessage {
+  kind: "PubInvites";
+  pub_id: string;
+  list: PubInvite[];
+}
+
 interface TablesMessage {
   kind: "Tables";
   list: Table[];
@@ -38,6 +72,285 @@ interface DataMessage {
   content: string;
 }
 
+interface OfferMessage {
+  kind: "Offer";
+  author: string;
+  sdp: string;
+}
+
+interface AnswerMessage {
+  kind: "Answer";
+  author: string;
+  sdp: string;
+}
+
+interface IceCandidateMessage {
+  kind: "IceCandidate";
+  author: string;
+  candidate: RTCIceCandidateInit;
+}
+
+interface RenegotiateMessage {
+  kind: "Renegotiate";
+  author: string;
+}
+
+interface HangupMessage {
+  kind: "Hangup";
+  author: string;
+}
+
+interface SessionMessage {
+  kind: "Session";
+  session_id: string;
+}
+
+interface SessionReplacedMessage {
+  kind: "SessionReplaced";
+  session_id: string;
+}
+
+interface PubCreatedMessage {
+  kind: "PubCreated";
+  data: Pub;
+}
+
+interface PubDeletedMessage {
+  kind: "PubDeleted";
+  pub_id: string;
+}
+
+interface PubOccupancyMessage {
+  kind: "PubOccupancy";
+  pub_id: string;
+  persons: string[];
+}
+
+interface PersonJoinedPubMessage {
+  kind: "PersonJoinedPub";
+  pub_id: string;
+  data: Person;
+}
+
+interface PersonLeftPubMessage {
+  kind: "PersonLeftPub";
+  pub_id: string;
+  person_id: string;
+}
+
+interface PersonJoinedTableMessage {
+  kind: "PersonJoinedTable";
+  table_id: string;
+  person_id: string;
+}
+
+interface PersonLeftTableMessage {
+  kind: "PersonLeftTable";
+  table_id: string;
+  person_id: string;
+}
+
+interface TableCreatedMessage {
+  kind: "TableCreated";
+  data: Table;
+}
+
+interface PubUpdatedMessage {
+  kind: "PubUpdated";
+  data: Pub;
+}
+
+interface TableUpdatedMessage {
+  kind: "TableUpdated";
+  data: Table;
+}
+
+interface TableDeletedMessage {
+  kind: "TableDeleted";
+  pub_id: string;
+  table_id: string;
+}
+
+interface RolesMessage {
+  kind: "Roles";
+  pub_id: string;
+  list: PubRoleEntry[];
+}
+
+interface RoleChangedMessage {
+  kind: "RoleChanged";
+  pub_id: string;
+  person_id: string;
+  role: PubRole;
+}
+
+interface KickedMessage {
+  kind: "Kicked";
+  pub_id: string;
+  by: string;
+}
+
+interface BannedMessage {
+  kind: "Banned";
+  pub_id: string;
+  by: string;
+  expires: string | null;
+}
+
+interface UnbannedMessage {
+  kind: "Unbanned";
+  pub_id: string;
+}
+
+interface MutedMessage {
+  kind: "Muted";
+  pub_id: string;
+  by: string;
+}
+
+interface UnmutedMessage {
+  kind: "Unmuted";
+  pub_id: string;
+}
+
+interface SeatedMessage {
+  kind: "Seated";
+  table_id: string;
+}
+
+interface QueuePositionMessage {
+  kind: "QueuePosition";
+  table_id: string;
+  position: number;
+  length: number;
+}
+
+interface LeftQueueMessage {
+  kind: "LeftQueue";
+  table_id: string;
+}
+
+interface TableInviteMessage {
+  kind: "TableInvite";
+  table_id: string;
+  by: string;
+}
+
+interface KnockMessage {
+  kind: "Knock";
+  table_id: string;
+  person_id: string;
+}
+
+interface AdmittedMessage {
+  kind: "Admitted";
+  table_id: string;
+  by: string;
+}
+
+interface DeniedMessage {
+  kind: "Denied";
+  table_id: string;
+  by: string;
+}
+
+interface ChatMessageMessage {
+  kind: "ChatMessage";
+  data: ChatMessage;
+}
+
+interface ChatHistoryMessage {
+  kind: "ChatHistory";
+  scope: ChatScope;
+  list: ChatMessage[];
+}
+
+interface ChatMessageUpdatedMessage {
+  kind: "ChatMessageUpdated";
+  data: ChatMessage;
+}
+
+interface MessageVersionsMessage {
+  kind: "MessageVersions";
+  message_id: number;
+  list: ChatMessageVersion[];
+}
+
+interface SearchResultsMessage {
+  kind: "SearchResults";
+  pub_id: string;
+  query: string;
+  list: ChatSearchHit[];
+}
+
+interface DirectMessageMessage {
+  kind: "DirectMessage";
+  data: DirectMessage;
+}
+
+interface InboxMessage {
+  kind: "Inbox";
+  list: Conversation[];
+  unread: number;
+}
+
+interface DirectMessageHistoryMessage {
+  kind: "DirectMessageHistory";
+  user_id: string;
+  list: DirectMessage[];
+}
+
+interface DirectMessageReceiptMessage {
+  kind: "DirectMessageReceipt";
+  sender_id: string;
+  recipient_id: string;
+  up_to: number;
+  receipt: Receipt;
+  at: string;
+}
+
+interface NotificationMessage {
+  kind: "Notification";
+  data: Notification;
+}
+
+interface NotificationsMessage {
+  kind: "Notifications";
+  list: Notification[];
+  unread: number;
+}
+
+interface NotificationsReadMessage {
+  kind: "NotificationsRead";
+  up_to: number;
+  unread: number;
+}
+
+interface BlocksMessage {
+  kind: "Blocks";
+  list: Block[];
+}
+
+interface AuditLogMessage {
+  kind: "AuditLog";
+  pub_id: string;
+  list: AuditEvent[];
+}
+
+interface ErrorMessage {
+  kind: "Error";
+  code:
+    | "NotFound"
+    | "Conflict"
+    | "Unauthorized"
+    | "Forbidden"
+    | "InvalidInput"
+    | "TableFull"
+    | "Internal";
+  message: string;
+  command: string | null;
+}
+
 export type SocketMessage =
   | PubsMessage
   | TablesMessage
@@ -45,49 +358,112 @@ export type SocketMessage =
   | CreatePubMessage
   | CreateTableMessage
   | PersonMessage
-  | DataMessage;
+  | DataMessage
+  | OfferMessage
+  | AnswerMessage
+  | IceCandidateMessage
+  | RenegotiateMessage
+  | HangupMessage
+  | SessionMessage
+  | SessionReplacedMessage
+  | PubCreatedMessage
+  | PubDeletedMessage
+  | PubOccupancyMessage
+  | PersonJoinedPubMessage
+  | PersonLeftPubMessage
+  | PersonJoinedTableMessage
+  | PersonLeftTableMessage
+  | TableCreatedMessage
+  | TableDeletedMessage
+  | PubMessage
+  | PubUpdatedMessage
+  | TableUpdatedMessage
+  | PubInviteMessage
+  | PubInvitesMessage
+  | RolesMessage
+  | RoleChangedMessage
+  | KickedMessage
+  | BannedMessage
+  | UnbannedMessage
+  | MutedMessage
+  | UnmutedMessage
+  | SeatedMessage
+  | QueuePositionMessage
+  | LeftQueueMessage
+  | TableInviteMessage
+  | KnockMessage
+  | AdmittedMessage
+  | DeniedMessage
+  | ChatMessageMessage
+  | ChatHistoryMessage
+  | ChatMessageUpdatedMessage
+  | MessageVersionsMessage
+  | SearchResultsMessage
+  | DirectMessageMessage
+  | InboxMessage
+  | DirectMessageHistoryMessage
+  | DirectMessageReceiptMessage
+  | NotificationMessage
+  | NotificationsMessage
+  | NotificationsReadMessage
+  | BlocksMessage
+  | AuditLogMessage
+  | ErrorMessage;
 
-function handleDataMsg(websocket: WS, peer: string, encoded_msg: string) {
-  console.log("video msg from", peer, encoded_msg);
-  const msg: RTCSessionDescription | RTCIceCandidate = JSON.parse(encoded_msg);
-  const conn = useUIStore((s) => {
-    if (peer in s.peers) {
-      return s.peers[peer];
-    } else {
-      return null;
-    }
-  });
+function getPeer(peer: string): RTCPeerConnection | null {
+  const peers = useUIStore.getState().peers;
+  if (peer in peers) {
+    return peers[peer];
+  }
+  console.warn(`Don't have a connection for ${peer}`);
+  return null;
+}
+
+function handleOffer(websocket: WS, peer: string, sdp: string) {
+  const conn = getPeer(peer);
   if (conn === null) {
-    console.warn(`Don't have a connection for ${peer}`);
     return;
   }
-  if (msg === null) {
-    console.log("Null video message from", peer);
-  } else if (msg.type == "offer") {
-    conn.setRemoteDescription(msg);
-    conn.createAnswer().then((answer) => {
-      console.log("answer", answer);
-      conn.setLocalDescription(answer).then(() => {
-        send(websocket, peer, JSON.stringify(conn.localDescription));
-      });
+  conn.setRemoteDescription({ type: "offer", sdp });
+  conn.createAnswer().then((answerDesc) => {
+    console.log("answer", answerDesc);
+    conn.setLocalDescription(answerDesc).then(() => {
+      if (conn.localDescription !== null) {
+        answer(websocket, peer, conn.localDescription.sdp);
+      }
     });
-  } else if (msg.type == "answer") {
-    conn.setRemoteDescription(msg);
-  } else if ("candidate" in msg) {
-    conn.addIceCandidate(msg);
-  } else {
-    console.log("video msg from", peer, JSON.stringify(msg));
+  });
+}
+
+function handleAnswer(peer: string, sdp: string) {
+  const conn = getPeer(peer);
+  if (conn !== null) {
+    conn.setRemoteDescription({ type: "answer", sdp });
+  }
+}
+
+function handleIceCandidate(peer: string, candidate: RTCIceCandidateInit) {
+  const conn = getPeer(peer);
+  if (conn !== null) {
+    conn.addIceCandidate(candidate);
   }
 }
 
 export const doMessage = (websocket: WS, message: SocketMessage) => {
   switch (message.kind) {
-    case "Pubs":
+    case "Pubs": {
+      // Hang on to the pub we're in, even if the lobby can't see it
+      const current = useUIStore.getState().currentPub();
+      const list = message.list;
       useUIStore.setState((s) => ({
         ...s,
-        pubs: message.list,
+        pubs:
+          current === null || list.some((p) => p.id == current.id)
+            ? list
+            : [...list, current],
       }));
       break;
+    }
     case "Tables":
       useUIStore.setState((s) => ({
         ...s,
@@ -97,18 +473,136 @@ export const doMessage = (websocket: WS, message: SocketMessage) => {
     case "Pong": {
       break;
     }
+    case "Session": {
+      console.debug("Session id", message.session_id);
+      break;
+    }
+    case "SessionReplaced": {
+      console.warn(
+        `Camera has been taken over by session ${message.session_id}`
+      );
+      for (const conn of Object.values(useUIStore.getState().peers)) {
+        conn.close();
+      }
+      break;
+    }
     case "CreatePub":
+    case "PubCreated":
+    case "Pub": {
+      const pub = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        pubs: [...s.pubs.filter((p) => p.id != pub.id), pub],
+      }));
+      break;
+    }
+    case "PubDeleted": {
+      const pubId = message.pub_id;
+      useUIStore.setState((s) => ({
+        ...s,
+        pubs: s.pubs.filter((p) => p.id != pubId),
+      }));
+      break;
+    }
+    case "PubUpdated": {
+      const pub = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        pubs: s.pubs.map((p) => (p.id == pub.id ? pub : p)),
+      }));
+      break;
+    }
+    case "PubOccupancy": {
+      const { pub_id, persons } = message;
       useUIStore.setState((s) => ({
         ...s,
-        pubs: [...s.pubs, message.data],
+        pubs: s.pubs.map((p) => (p.id == pub_id ? { ...p, persons } : p)),
       }));
       break;
+    }
     case "CreateTable":
+    case "TableCreated": {
+      const table = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        tables: [...s.tables.filter((t) => t.id != table.id), table],
+      }));
+      break;
+    }
+    case "TableUpdated": {
+      const table = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        tables: s.tables.map((t) => (t.id == table.id ? table : t)),
+      }));
+      break;
+    }
+    case "TableDeleted": {
+      const tableId = message.table_id;
+      useUIStore.setState((s) => ({
+        ...s,
+        tables: s.tables.filter((t) => t.id != tableId),
+      }));
+      break;
+    }
+    case "PersonJoinedPub": {
+      const person = message.data;
+      useUIStore.setState(
+        produce((s) => {
+          s.persons[person.id] = person;
+        })
+      );
+      break;
+    }
+    case "PersonLeftPub": {
+      const personId = message.person_id;
+      useUIStore.setState(
+        produce((s) => {
+          if (personId in s.persons) {
+            s.persons[personId].pub_id = null;
+            s.persons[personId].table_id = null;
+          }
+        })
+      );
+      break;
+    }
+    case "PersonJoinedTable": {
+      const { table_id, person_id } = message;
+      if (person_id == useUIStore.getState().peerId) {
+        useUIStore.setState((s) => ({
+          ...s,
+          tableError: null,
+          invites: s.invites.filter((t) => t != table_id),
+        }));
+      }
       useUIStore.setState((s) => ({
         ...s,
-        tables: [...s.tables, message.data],
+        knocks: s.knocks.filter(
+          (k) => k.table_id != table_id || k.person_id != person_id
+        ),
+        tables: s.tables.map((t) =>
+          t.id == table_id
+            ? {
+                ...t,
+                persons: [...t.persons.filter((p) => p != person_id), person_id],
+              }
+            : t
+        ),
       }));
       break;
+    }
+    case "PersonLeftTable": {
+      const { table_id, person_id } = message;
+      useUIStore.setState((s) => ({
+        ...s,
+        tables: s.tables.map((t) =>
+          t.id == table_id
+            ? { ...t, persons: t.persons.filter((p) => p != person_id) }
+            : t
+        ),
+      }));
+      break;
+    }
     case "Person": {
       const person = message.data;
       useUIStore.setState(
@@ -119,7 +613,288 @@ export const doMessage = (websocket: WS, message: SocketMessage) => {
       break;
     }
     case "Data": {
-      handleDataMsg(websocket, message.author, message.content);
+      console.log("data msg from", message.author, message.content);
+      break;
+    }
+    case "Offer": {
+      handleOffer(websocket, message.author, message.sdp);
+      break;
+    }
+    case "Answer": {
+      handleAnswer(message.author, message.sdp);
+      break;
+    }
+    case "IceCandidate": {
+      handleIceCandidate(message.author, message.candidate);
+      break;
+    }
+    case "Renegotiate": {
+      const conn = getPeer(message.author);
+      if (conn !== null) {
+        conn.restartIce();
+      }
+      break;
+    }
+    case "Hangup": {
+      const conn = getPeer(message.author);
+      if (conn !== null) {
+        conn.close();
+      }
+      break;
+    }
+    case "PubInvite": {
+      const invite = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        pubInvites: [...s.pubInvites, invite],
+        inviteToken: { pub_id: invite.pub_id, token: message.token },
+      }));
+      break;
+    }
+    case "PubInvites": {
+      useUIStore.setState((s) => ({ ...s, pubInvites: message.list }));
+      break;
+    }
+    case "Roles": {
+      const roles: { [key: string]: PubRole } = {};
+      for (const entry of message.list) {
+        roles[entry.person_id] = entry.role;
+      }
+      useUIStore.setState((s) => ({ ...s, roles }));
+      break;
+    }
+    case "RoleChanged": {
+      const { person_id, role } = message;
+      useUIStore.setState((s) => ({
+        ...s,
+        roles: { ...s.roles, [person_id]: role },
+      }));
+      break;
+    }
+    // Being thrown out also sends our updated Person, which takes us home
+    case "Kicked": {
+      console.warn(`Kicked out of ${message.pub_id} by ${message.by}`);
+      break;
+    }
+    case "Banned": {
+      console.warn(
+        `Banned from ${message.pub_id} by ${message.by} until ${
+          message.expires ?? "further notice"
+        }`
+      );
+      break;
+    }
+    case "Unbanned": {
+      console.info(`Ban from ${message.pub_id} lifted`);
+      break;
+    }
+    case "Muted": {
+      console.warn(`Muted in ${message.pub_id} by ${message.by}`);
+      break;
+    }
+    case "Unmuted": {
+      console.info(`No longer muted in ${message.pub_id}`);
+      break;
+    }
+    case "QueuePosition": {
+      const { table_id, position, length } = message;
+      useUIStore.setState((s) => ({
+        ...s,
+        queue: { table_id, position, length },
+      }));
+      break;
+    }
+    case "Seated":
+    case "LeftQueue": {
+      useUIStore.setState((s) => ({ ...s, queue: null }));
+      break;
+    }
+    case "TableInvite": {
+      const tableId = message.table_id;
+      useUIStore.setState((s) => ({
+        ...s,
+        invites: [...s.invites.filter((t) => t != tableId), tableId],
+      }));
+      break;
+    }
+    case "Knock": {
+      const { table_id, person_id } = message;
+      useUIStore.setState((s) => ({
+        ...s,
+        knocks: [
+          ...s.knocks.filter(
+            (k) => k.table_id != table_id || k.person_id != person_id
+          ),
+          { table_id, person_id },
+        ],
+      }));
+      break;
+    }
+    case "Admitted":
+    case "Denied": {
+      const tableId = message.table_id;
+      if (message.kind == "Denied") {
+        useUIStore.setState((s) => ({
+          ...s,
+          tableError: "You weren't let in to that table",
+        }));
+      }
+      useUIStore.setState((s) => ({
+        ...s,
+        invites: s.invites.filter((t) => t != tableId),
+      }));
+      break;
+    }
+    case "ChatMessage":
+    case "ChatMessageUpdated":
+    case "ChatHistory": {
+      const incoming =
+        message.kind == "ChatHistory" ? message.list : [message.data];
+      useUIStore.setState((s) => ({
+        ...s,
+        chat: [
+          ...s.chat.filter((m) => !incoming.some((i) => i.id == m.id)),
+          ...incoming,
+        ].sort((x, y) => x.id - y.id),
+      }));
+      break;
+    }
+    case "MessageVersions": {
+      console.table(message.list);
+      break;
+    }
+    case "SearchResults": {
+      useUIStore.setState((s) => ({
+        ...s,
+        searchResults: {
+          pub_id: message.pub_id,
+          query: message.query,
+          list: message.list,
+        },
+      }));
+      break;
+    }
+    case "DirectMessage":
+    case "DirectMessageHistory": {
+      const incoming =
+        message.kind == "DirectMessage" ? [message.data] : message.list;
+      useUIStore.setState((s) => ({
+        ...s,
+        directMessages: [
+          ...s.directMessages.filter(
+            (m) => !incoming.some((i) => i.id == m.id)
+          ),
+          ...incoming,
+        ].sort((x, y) => x.id - y.id),
+      }));
+      if (message.kind == "DirectMessage") {
+        const dm = message.data;
+        useUIStore.setState((s) => {
+          const otherId =
+            dm.sender_id == s.peerId ? dm.recipient_id : dm.sender_id;
+          const previous = s.inbox.find((c) => c.person_id == otherId);
+          const unread =
+            (previous?.unread ?? 0) + (dm.recipient_id == s.peerId ? 1 : 0);
+          return {
+            ...s,
+            inbox: [
+              { person_id: otherId, last: dm, unread },
+              ...s.inbox.filter((c) => c.person_id != otherId),
+            ],
+          };
+        });
+      }
+      break;
+    }
+    case "Inbox": {
+      useUIStore.setState((s) => ({ ...s, inbox: message.list }));
+      break;
+    }
+    case "DirectMessageReceipt": {
+      const { sender_id, recipient_id, up_to, receipt, at } = message;
+      const applies = (m: DirectMessage) =>
+        m.sender_id == sender_id &&
+        m.recipient_id == recipient_id &&
+        m.id <= up_to;
+      useUIStore.setState((s) => ({
+        ...s,
+        directMessages: s.directMessages.map((m) =>
+          applies(m)
+            ? {
+                ...m,
+                delivered: m.delivered ?? at,
+                read: receipt == "Read" ? m.read ?? at : m.read,
+              }
+            : m
+        ),
+        // Our other sessions reading things
+        inbox:
+          receipt == "Read" && recipient_id == s.peerId
+            ? s.inbox.map((c) =>
+                c.person_id == sender_id && c.last.id <= up_to
+                  ? { ...c, unread: 0 }
+                  : c
+              )
+            : s.inbox,
+      }));
+      break;
+    }
+    case "Notification": {
+      const notification = message.data;
+      useUIStore.setState((s) => ({
+        ...s,
+        notifications: [
+          notification,
+          ...s.notifications.filter((n) => n.id != notification.id),
+        ],
+        unreadNotifications: s.unreadNotifications + 1,
+      }));
+      break;
+    }
+    case "Notifications": {
+      const incoming = message.list;
+      useUIStore.setState((s) => ({
+        ...s,
+        notifications: [
+          ...s.notifications.filter(
+            (n) => !incoming.some((i) => i.id == n.id)
+          ),
+          ...incoming,
+        ].sort((x, y) => y.id - x.id),
+        unreadNotifications: message.unread,
+      }));
+      break;
+    }
+    case "NotificationsRead": {
+      const { up_to, unread } = message;
+      useUIStore.setState((s) => ({
+        ...s,
+        notifications: s.notifications.map((n) =>
+          n.id <= up_to && n.read === null
+            ? { ...n, read: new Date().toISOString() }
+            : n
+        ),
+        unreadNotifications: unread,
+      }));
+      break;
+    }
+    case "Blocks": {
+      useUIStore.setState((s) => ({ ...s, blocks: message.list }));
+      break;
+    }
+    case "AuditLog": {
+      console.table(message.list);
+      break;
+    }
+    case "Error": {
+      if (message.code == "TableFull") {
+        useUIStore.setState((s) => ({ ...s, tableError: message.message }));
+      }
+      console.error(
+        `Error from ${message.command ?? "unknown command"} (${message.code}): ${
+          message.message
+        }`
+      );
       break;
     }
 